@@ -0,0 +1,249 @@
+use std::fmt;
+use std::path::Path;
+
+use macroquad::math::Rect;
+use serde::de::{MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+
+use crate::sprite_library::{self, Animation, AssetError, Frame, Playback};
+
+/// Aseprite JSON export of a spritesheet ("Array" or "Hash" frames)
+#[derive(Deserialize)]
+struct AsepriteSheet {
+    frames: AsepriteFrames,
+    meta: Meta,
+}
+
+#[derive(Deserialize)]
+struct AsepriteFrame {
+    frame: FrameRect,
+    /// In milliseconds
+    duration: u32,
+    /// Trimmed or rotated frames are not drawn from `frame` as is, which
+    /// isn't supported
+    #[serde(default)]
+    trimmed: bool,
+    #[serde(default)]
+    rotated: bool,
+}
+
+#[derive(Deserialize)]
+struct FrameRect {
+    x: i32,
+    y: i32,
+    w: i32,
+    h: i32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Meta {
+    #[serde(default)]
+    frame_tags: Vec<FrameTag>,
+    #[serde(default)]
+    layers: Vec<Layer>,
+}
+
+/// A layer, only exported with its cels when they hold user data
+#[derive(Deserialize)]
+struct Layer {
+    #[serde(default)]
+    cels: Vec<Cel>,
+}
+
+#[derive(Deserialize)]
+struct Cel {
+    frame: usize,
+    #[serde(default)]
+    data: String,
+}
+
+#[derive(Deserialize)]
+struct FrameTag {
+    name: String,
+    from: usize,
+    to: usize,
+    direction: String,
+}
+
+/// Frames in the order of the file
+///
+/// With the "Hash" layout frames are a map of file names, which must not be
+/// sorted by name ("10" would come before "2").
+struct AsepriteFrames(Vec<AsepriteFrame>);
+
+impl<'de> Deserialize<'de> for AsepriteFrames {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FramesVisitor;
+
+        impl<'de> Visitor<'de> for FramesVisitor {
+            type Value = AsepriteFrames;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a list or a map of frames")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut frames = Vec::new();
+                while let Some(frame) = seq.next_element()? {
+                    frames.push(frame);
+                }
+                Ok(AsepriteFrames(frames))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut frames = Vec::new();
+                while let Some((_name, frame)) = map.next_entry::<String, AsepriteFrame>()? {
+                    frames.push(frame);
+                }
+                Ok(AsepriteFrames(frames))
+            }
+        }
+
+        deserializer.deserialize_any(FramesVisitor)
+    }
+}
+
+/// Read an Aseprite JSON export and build one animation per frame tag
+///
+/// Frames must be neither trimmed nor rotated in the export. Events of a
+/// frame are taken from the user data of its cels, as a comma separated list
+/// of names. Samples of both layouts are in `assets/aseprite`.
+pub fn import(path: &Path) -> Result<Vec<(String, Animation)>, AssetError> {
+    let sheet: AsepriteSheet = sprite_library::read_json(path)?;
+    let error = |message: String| AssetError::Aseprite {
+        path: path.to_path_buf(),
+        message,
+    };
+
+    if sheet.meta.frame_tags.is_empty() {
+        return Err(error("no frame tag, animations are made from tags".to_string()));
+    }
+    if let Some(index) = sheet.frames.0.iter().position(|f| f.trimmed || f.rotated) {
+        return Err(error(format!("frame {} is trimmed or rotated, export it without trim nor rotation", index)));
+    }
+
+    let mut frames: Vec<Frame> = sheet
+        .frames
+        .0
+        .iter()
+        .map(|f| Frame {
+            source: Rect::new(f.frame.x as f32, f.frame.y as f32, f.frame.w as f32, f.frame.h as f32),
+            duration: f.duration as f32 / 1000.0,
+            events: Vec::new(),
+        })
+        .collect();
+
+    for cel in sheet.meta.layers.iter().flat_map(|l| l.cels.iter()) {
+        let frame = frames.get_mut(cel.frame).ok_or_else(|| {
+            error(format!("a cel is on frame {}, but there are {} frames", cel.frame, sheet.frames.0.len()))
+        })?;
+        frame
+            .events
+            .extend(cel.data.split(',').map(|e| e.trim()).filter(|e| !e.is_empty()).map(|e| e.to_string()));
+    }
+
+    let mut animations = Vec::new();
+    for tag in sheet.meta.frame_tags.iter() {
+        if tag.from > tag.to || tag.to >= frames.len() {
+            return Err(error(format!(
+                "tag {} uses frames {} to {}, but there are {} frames",
+                tag.name, tag.from, tag.to, frames.len()
+            )));
+        }
+
+        let mut tag_frames = frames[tag.from..=tag.to].to_vec();
+        let playback = match tag.direction.as_str() {
+            "forward" => Playback::Loop,
+            "reverse" => {
+                tag_frames.reverse();
+                Playback::Loop
+            }
+            "pingpong" => Playback::PingPong,
+            "pingpong_reverse" => {
+                tag_frames.reverse();
+                Playback::PingPong
+            }
+            other => return Err(error(format!("unknown direction {} for tag {}", other, tag.name))),
+        };
+
+        animations.push((tag.name.clone(), Animation { frames: tag_frames, playback }));
+    }
+
+    Ok(animations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ARRAY: &str = "./assets/aseprite/sample_array.json";
+    const HASH: &str = "./assets/aseprite/sample_hash.json";
+
+    fn animation<'a>(animations: &'a [(String, Animation)], name: &str) -> &'a Animation {
+        &animations.iter().find(|(tag, _)| tag == name).unwrap().1
+    }
+
+    /// Where the frames of `animation` are, by index of the sample's frames
+    fn frame_indexes(animation: &Animation) -> Vec<usize> {
+        animation.frames.iter().map(|f| (f.source.x / 16.0 + f.source.y / 16.0 * 4.0) as usize).collect()
+    }
+
+    #[test]
+    fn array_and_hash_exports_give_the_same_animations() {
+        let array = import(Path::new(ARRAY)).unwrap();
+        let hash = import(Path::new(HASH)).unwrap();
+        let names: Vec<&str> = array.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["walk", "walk_back", "nod", "blink"]);
+        for ((array_name, a), (hash_name, h)) in array.iter().zip(hash.iter()) {
+            assert_eq!(array_name, hash_name);
+            assert_eq!(a.playback, h.playback);
+            assert_eq!(frame_indexes(a), frame_indexes(h), "{}", array_name);
+            for (fa, fh) in a.frames.iter().zip(h.frames.iter()) {
+                assert_eq!((fa.duration, &fa.events), (fh.duration, &fh.events));
+            }
+        }
+    }
+
+    #[test]
+    fn tags_give_frames_playback_and_events() {
+        let animations = import(Path::new(HASH)).unwrap();
+
+        let walk = animation(&animations, "walk");
+        assert_eq!(walk.playback, Playback::Loop);
+        assert_eq!(frame_indexes(walk), [0, 1, 2, 3]);
+        assert_eq!(walk.frames[0].source, Rect::new(0.0, 0.0, 16.0, 16.0));
+        assert_eq!(walk.frames[0].duration, 0.1);
+        let events: Vec<Vec<String>> = walk.frames.iter().map(|f| f.events.clone()).collect();
+        assert_eq!(events, [vec![], vec!["footstep"], vec![], vec!["footstep", "bite"]]);
+
+        let walk_back = animation(&animations, "walk_back");
+        assert_eq!(walk_back.playback, Playback::Loop);
+        assert_eq!(frame_indexes(walk_back), [3, 2, 1, 0]);
+        assert_eq!(walk_back.frames[0].events, ["footstep", "bite"]);
+
+        let nod = animation(&animations, "nod");
+        assert_eq!(nod.playback, Playback::PingPong);
+        assert_eq!(frame_indexes(nod), [4, 5, 6, 7]);
+        assert_eq!(nod.frames[0].duration, 0.12);
+
+        // Frame "hero 10" stays after "hero 9", and has the cel of another layer
+        let blink = animation(&animations, "blink");
+        assert_eq!(blink.playback, Playback::PingPong);
+        assert_eq!(frame_indexes(blink), [11, 10, 9, 8]);
+        assert_eq!(blink.frames[1].events, ["dust"]);
+    }
+
+    #[test]
+    fn trimmed_or_rotated_frames_are_refused() {
+        let sample = std::fs::read_to_string(ARRAY).unwrap();
+        for flag in ["trimmed", "rotated"] {
+            let export = sample.replacen(&format!("\"{}\": false", flag), &format!("\"{}\": true", flag), 1);
+            let path = std::env::temp_dir().join(format!("keep_you_sheep_{}_{}.json", flag, std::process::id()));
+            std::fs::write(&path, export).unwrap();
+            let result = import(&path);
+            std::fs::remove_file(&path).unwrap();
+            assert!(matches!(result, Err(AssetError::Aseprite { .. })), "{} frame imported", flag);
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::error::Error;
+use std::time::Instant;
+
+use macroquad::math::Rect;
+use macroquad::texture::Image;
+
+use crate::entities::{Entity, EntityType};
+use crate::flock::FlockWeights;
+use crate::input::PlayerInput;
+use crate::level::Level;
+use crate::pathfinding::Navigation;
+use crate::puppet_master;
+use crate::rng::Rng;
+use crate::sprite_library::read_atlas;
+use crate::{ATLAS, FIXED_DT, SPRITESHEET, TICK_RATE};
+
+/// Run the benchmark named `name`, printing its results
+///
+/// Benchmarks are meant to be run on a release build.
+pub fn run(name: &str) -> Result<(), Box<dyn Error>> {
+    match name {
+        "collision" => collision(),
+        "entities" => entities(),
+        _ => Err(format!("unknown benchmark {}, available: collision, entities", name).into()),
+    }
+}
+
+/// Level collision queries on bigger and bigger random levels
+///
+/// The queries are answered from the cells under the rectangle, the linear
+/// scan of every wall is only there to compare with, and to check both give
+/// the same answers.
+fn collision() -> Result<(), Box<dyn Error>> {
+    const GRID_SIZE: f32 = 16.0;
+    const QUERIES: usize = 100_000;
+    const LINEAR_QUERIES: usize = 200;
+
+    let mut rng = Rng::new(0);
+    println!("{:>6} {:>10} {:>14} {:>14}", "side", "walls", "grid ns/query", "scan ns/query");
+    for side in [32, 128, 512, 2048] {
+        // A wall on a fifth of the cells
+        let grid: Vec<u8> = (0..side * side).map(|_| (rng.gen_range(0, 5) == 0) as u8).collect();
+        let level = Level::from_grid(side, side, GRID_SIZE, grid);
+        let walls: Vec<Rect> = (0..side * side)
+            .filter(|i| level.collision_grid[*i] == 1)
+            .map(|i| level.cell_box(i % side, i / side))
+            .collect();
+
+        // Rectangles the size of an entity's collision box
+        let queries: Vec<Rect> = (0..QUERIES)
+            .map(|_| Rect::new(rng.gen_range(-16.0, level.width), rng.gen_range(-16.0, level.height), 12.0, 6.0))
+            .collect();
+
+        let start = Instant::now();
+        let hits: Vec<bool> = queries.iter().map(|r| level.cbox_overlaps_with_value(1, *r)).collect();
+        let grid_time = start.elapsed().as_nanos() as f64 / QUERIES as f64;
+
+        let start = Instant::now();
+        for (r, hit) in queries.iter().zip(hits.iter()).take(LINEAR_QUERIES) {
+            if walls.iter().any(|w| r.overlaps(w)) != *hit {
+                return Err(format!("grid and scan disagree on {:?} in a {}x{} level", r, side, side).into());
+            }
+        }
+        let scan_time = start.elapsed().as_nanos() as f64 / LINEAR_QUERIES as f64;
+
+        println!("{:>6} {:>10} {:>14.1} {:>14.1}", side, walls.len(), grid_time, scan_time);
+    }
+    Ok(())
+}
+
+/// Simulation ticks with more and more entities in a big walled field
+///
+/// Every entity has its own cell at the start, one in ten is a wolf. The
+/// game must keep up with 1000 entities at the tick rate.
+fn entities() -> Result<(), Box<dyn Error>> {
+    const SIDE: usize = 128;
+    const GRID_SIZE: f32 = 16.0;
+    const TICKS: u32 = 300;
+
+    let image = Image::from_file_with_format(SPRITESHEET, None);
+    let atlas = read_atlas(ATLAS, image.width() as f32, image.height() as f32)?;
+    // Fences across the field, with gaps, so that the wolves look for ways around
+    let fence = |x: usize, y: usize| x % 16 == 8 && y % 32 > 4;
+    let grid: Vec<u8> = (0..SIDE * SIDE)
+        .map(|i| (i % SIDE == 0 || i % SIDE == SIDE - 1 || i / SIDE == 0 || i / SIDE == SIDE - 1 || fence(i % SIDE, i / SIDE)) as u8)
+        .collect();
+    let level = Level::from_grid(SIDE, SIDE, GRID_SIZE, grid);
+
+    let mut rng = Rng::new(0);
+    let mut navigation = Navigation::default();
+    let weights = FlockWeights::default();
+    println!("{:>8} {:>10} {:>10}", "entities", "ms/tick", "ticks/s");
+    for count in [100, 500, 1000, 2000] {
+        let mut cells: Vec<usize> = (0..SIDE * SIDE).filter(|i| level.collision_grid[*i] == 0).collect();
+        let mut entities = Vec::with_capacity(count);
+        for id in 0..count {
+            let cell = cells.swap_remove(rng.gen_range(0, cells.len()));
+            let entity_type = if id % 10 == 0 { EntityType::Wolf } else { EntityType::Sheep };
+            let (x, y) = ((cell % SIDE) as f32 * GRID_SIZE, (cell / SIDE) as f32 * GRID_SIZE);
+            entities.push(Entity::new(x, y, entity_type, id as u32, &atlas)?);
+        }
+
+        let start = Instant::now();
+        for _ in 0..TICKS {
+            puppet_master::play(&mut entities, &level, &mut navigation, &weights, &[PlayerInput::default()], &mut rng, FIXED_DT);
+        }
+        let tick_time = start.elapsed().as_secs_f64() / TICKS as f64;
+
+        let verdict = if tick_time * TICK_RATE as f64 <= 1.0 { "" } else { "  slower than the tick rate" };
+        println!("{:>8} {:>10.3} {:>10.0}{}", count, tick_time * 1000.0, 1.0 / tick_time, verdict);
+    }
+    Ok(())
+}
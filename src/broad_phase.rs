@@ -0,0 +1,61 @@
+use macroquad::math::Rect;
+
+/// Uniform grid of rectangles, to only test the pairs that can collide
+///
+/// Each rectangle is put in every cell it covers, a query returns the
+/// rectangles of the cells covered by the query. Rectangles outside of the
+/// grid go to its border cells.
+pub struct BroadPhase {
+    cell_size: f32,
+    cols: usize,
+    rows: usize,
+    cells: Vec<Vec<usize>>,
+}
+
+impl BroadPhase {
+    /// Grid covering `width` x `height` pixels
+    pub fn new(width: f32, height: f32, cell_size: f32) -> Self {
+        let cols = ((width / cell_size).ceil() as usize).max(1);
+        let rows = ((height / cell_size).ceil() as usize).max(1);
+        BroadPhase {
+            cell_size,
+            cols,
+            rows,
+            cells: vec![Vec::new(); cols * rows],
+        }
+    }
+
+    /// Cells covered by `rect`, as (first column, last column, first row, last row)
+    fn cell_range(&self, rect: &Rect) -> (usize, usize, usize, usize) {
+        let cell = |v: f32, cells: usize| ((v / self.cell_size).floor().max(0.0) as usize).min(cells - 1);
+        (
+            cell(rect.x, self.cols),
+            cell(rect.x + rect.w, self.cols),
+            cell(rect.y, self.rows),
+            cell(rect.y + rect.h, self.rows),
+        )
+    }
+
+    pub fn insert(&mut self, index: usize, rect: &Rect) {
+        let (x_min, x_max, y_min, y_max) = self.cell_range(rect);
+        for y in y_min..=y_max {
+            for x in x_min..=x_max {
+                self.cells[x + y * self.cols].push(index);
+            }
+        }
+    }
+
+    /// Put in `found` the index of every rectangle that may touch `rect`,
+    /// sorted and without duplicates
+    pub fn query(&self, rect: &Rect, found: &mut Vec<usize>) {
+        found.clear();
+        let (x_min, x_max, y_min, y_max) = self.cell_range(rect);
+        for y in y_min..=y_max {
+            for x in x_min..=x_max {
+                found.extend_from_slice(&self.cells[x + y * self.cols]);
+            }
+        }
+        found.sort_unstable();
+        found.dedup();
+    }
+}
@@ -0,0 +1,112 @@
+use crate::flock::FlockWeights;
+use crate::ldtk::{LdtkLevel, LdtkProject};
+use crate::level::LevelError;
+
+/// Sheep to bring in the pen when a level doesn't set its `SheepGoal` field
+const DEFAULT_SHEEP_GOAL: i32 = 10;
+
+/// A level of the campaign, and what must be done to clear it
+pub struct Stage {
+    pub identifier: String,
+    /// Number of sheep that must be in the pen at the same time
+    pub sheep_goal: i32,
+    /// How the sheep flock in this level
+    pub flock: FlockWeights,
+}
+
+impl Stage {
+    pub fn is_cleared(&self, sheep_in: i32) -> bool {
+        sheep_in >= self.sheep_goal
+    }
+
+    /// True when too few sheep are left to ever clear the level
+    pub fn is_lost(&self, sheep_left: i32) -> bool {
+        sheep_left < self.sheep_goal
+    }
+}
+
+/// The ordered list of levels to play
+///
+/// Levels are played in the order of the LDtk project, so adding a level
+/// to the campaign is just adding it in the editor.
+pub struct Campaign {
+    stages: Vec<Stage>,
+    /// Stage the campaign was started at
+    start: usize,
+    current: usize,
+}
+
+impl Campaign {
+    /// Build the campaign from all the levels of the project, starting at
+    /// the level named `start`
+    pub fn from_project(project: &LdtkProject, start: &str) -> Result<Campaign, LevelError> {
+        let stages = project
+            .levels
+            .iter()
+            .map(|level| {
+                Ok(Stage {
+                    identifier: level.identifier.clone(),
+                    sheep_goal: level
+                        .field("SheepGoal")
+                        .and_then(|v| v.as_i64())
+                        .map(|v| v as i32)
+                        .unwrap_or(DEFAULT_SHEEP_GOAL),
+                    flock: flock_weights(level)?,
+                })
+            })
+            .collect::<Result<Vec<Stage>, LevelError>>()?;
+
+        let current = stages
+            .iter()
+            .position(|s| s.identifier == start)
+            .ok_or_else(|| LevelError::UnknownLevel {
+                identifier: start.to_string(),
+            })?;
+
+        Ok(Campaign { stages, start: current, current })
+    }
+
+    pub fn current(&self) -> &Stage {
+        &self.stages[self.current]
+    }
+
+    /// Go back to the level the campaign was started at
+    pub fn restart(&mut self) {
+        self.current = self.start;
+    }
+
+    /// Go to the next level, return false if there is no more level to play
+    pub fn advance(&mut self) -> bool {
+        if self.current + 1 < self.stages.len() {
+            self.current += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Flock weights of a level, from its number fields named `Flock` followed
+/// by the name of a weight, like `FlockSeparation`
+///
+/// Weights the level doesn't set keep their default value.
+fn flock_weights(level: &LdtkLevel) -> Result<FlockWeights, LevelError> {
+    let invalid = |field: &str, e: serde_json::Error| LevelError::InvalidField {
+        level: level.identifier.clone(),
+        entity: None,
+        field: field.to_string(),
+        message: e.to_string(),
+    };
+
+    let mut weights = serde_json::Map::new();
+    for field in level.field_instances.iter().filter(|f| !f.value.is_null()) {
+        let Some(name) = field.identifier.strip_prefix("Flock") else {
+            continue;
+        };
+        // Checked one by one, to tell which field is wrong
+        let weight = serde_json::Map::from_iter([(name.to_string(), field.value.clone())]);
+        serde_json::from_value::<FlockWeights>(weight.into()).map_err(|e| invalid(&field.identifier, e))?;
+        weights.insert(name.to_string(), field.value.clone());
+    }
+    serde_json::from_value(weights.into()).map_err(|e| invalid("Flock", e))
+}
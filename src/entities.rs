@@ -1,20 +1,30 @@
 use std::collections::HashMap;
+use std::rc::Rc;
 use macroquad::prelude::*;
 
 use crate::sprite::Sprite;
-use crate::sprite_library::{self, SpriteLibraryData};
+use crate::sprite_library::{self, Animation, AssetError, Atlas};
 
-use crate::puppet_master::Behaviour;
+use crate::mind::Transition;
+use crate::puppet_master::{mind, Behaviour};
+use crate::sweep::Contact;
 
-#[derive(Copy, Clone, PartialEq)]
+/// How fast an entity stops once it has no direction, per second
+const VELOCITY_DAMPING: f32 = 13.4;
+
+/// Colors multiplied to the heroes' sprites, so each player knows theirs
+const PLAYER_TINTS: [Color; 2] = [WHITE, Color::new(0.55, 0.75, 1.0, 1.0)];
+
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum EntityType {
     Hero,
     Sheep,
-    Wolf
+    Wolf,
+    Tree,
 }
 
 #[derive(Hash, PartialEq, Eq, Copy, Clone, Debug)]
-enum AnimationState {
+pub enum AnimationState {
     IdleLeft,
     IdleRight,
     IdleDown,
@@ -23,6 +33,8 @@ enum AnimationState {
     WalkRight,
     WalkUp,
     WalkDown,
+    // One-shot animations, not available for every entity type
+    Bleat,
 }
 
 #[derive(Clone)]
@@ -30,17 +42,41 @@ pub struct Entity {
     pub id: u32,
     pub entity_type: EntityType,
     pub position: Vec2,
+    /// Position at the start of the current tick, to draw between two ticks
+    pub previous_position: Vec2,
+    /// Height above `position`, the entity's place on the ground, in pixels
+    pub height: f32,
+    pub previous_height: f32,
+    /// In pixels per second
     pub velocity: Vec2,
+    /// In pixels per second
     pub max_speed: f32,
+    /// Multiplies the speed of what this entity throws
+    pub throw_strength: f32,
     pub direction: Vec2,
     sprite: Sprite,
-    animations: HashMap<AnimationState, SpriteLibraryData>,
+    /// Color multiplied to the sprite
+    pub tint: Color,
+    /// Shared, as entities are cloned by the puppet master every tick
+    animations: Rc<HashMap<AnimationState, Rc<Animation>>>,
     animation_state: AnimationState,
+    /// Animation state to go back to when the one-shot animation is over
+    one_shot_from: Option<AnimationState>,
+    /// Events fired by the animation during the last update
+    animation_events: Vec<String>,
     pub collision_box: Rect,
     pub behaviour: Behaviour,
+    /// Behaviours interrupted, the last one is resumed when the current one
+    /// is over
+    pub suspended: Vec<Behaviour>,
     pub collidable: bool,
+    pub carriable: bool,
     pub thing_carried: Option<u32>,
     pub transporter: Option<u32>,
+    /// What the entity ran into during the last tick
+    pub contacts: Vec<Contact>,
+    /// Taken off the map, the entity is removed at the end of the tick
+    pub gone: bool,
 }
 
 impl Entity {
@@ -49,69 +85,130 @@ impl Entity {
         y: f32,
         entity_type: EntityType,
         id: u32,
-        atlas: &HashMap<String, SpriteLibraryData>,
-    ) -> Self {
-        let animations = set_animation(&entity_type, atlas);
+        atlas: &Atlas,
+    ) -> Result<Self, AssetError> {
+        let animations = Rc::new(set_animation(&entity_type, atlas)?);
         let animation_state = AnimationState::WalkUp;
-        let mut sprite = Sprite::new(*animations.get(&animation_state).unwrap());
-        sprite.set_position_to(Vec2::new(x, y));
+        let sprite = Sprite::new(Rc::clone(animations.get(&animation_state).unwrap()));
 
         let mut entity = Entity {
             id,
             entity_type,
             position: Vec2::new(x, y),
+            previous_position: Vec2::new(x, y),
+            height: 0.0,
+            previous_height: 0.0,
             velocity: Vec2::ZERO,
-            max_speed: 1.0,
+            max_speed: 60.0,
+            throw_strength: 1.0,
             direction: Vec2::ZERO,
             animations,
             animation_state,
+            one_shot_from: None,
+            animation_events: Vec::new(),
             sprite,
+            tint: WHITE,
             collision_box: Rect::new(2.0, 10.0, 12.0, 6.0),
-            behaviour: Behaviour::Playable,
+            behaviour: Behaviour::Playable { player: 0 },
+            suspended: Vec::new(),
             collidable: true,
+            carriable: true,
             thing_carried: None,
             transporter: None,
+            contacts: Vec::new(),
+            gone: false,
         };
 
         match entity_type {
             EntityType::Sheep => sheep_incubator(&mut entity),
-            EntityType::Hero => {},
+            EntityType::Hero => hero_incubator(&mut entity),
             EntityType::Wolf => wolf_incubator(&mut entity),
+            EntityType::Tree => tree_incubator(&mut entity),
         }
 
-        entity
+        Ok(entity)
     }
 
-    pub fn render(&mut self, texture: Texture2D, scale: f32) {
-        self.sprite.draw_sprite(texture, scale);
+    pub fn update_animation(&mut self, dt: f32) {
+        self.animation_events.clear();
+        self.sprite.update(dt, &mut self.animation_events);
+
+        if self.sprite.is_finished() {
+            if let Some(previous) = self.one_shot_from.take() {
+                self.set_animation_state(previous);
+            }
+        }
+    }
+
+    /// Play the animation of `state` once, then go back to the current one
+    ///
+    /// Return false if the entity has no such animation or is already playing
+    /// a one-shot animation.
+    pub fn play_once(&mut self, state: AnimationState) -> bool {
+        if self.one_shot_from.is_some() || !self.animations.contains_key(&state) {
+            return false;
+        }
+        self.one_shot_from = Some(self.animation_state);
+        self.set_animation_state(state);
+        // Whatever its playback, so that it ends
+        self.sprite.play_once();
+        true
+    }
+
+    /// True if the animation fired the event `name` during the last update
+    pub fn has_event(&self, name: &str) -> bool {
+        self.animation_events.iter().any(|e| e == name)
+    }
+
+    fn set_animation_state(&mut self, state: AnimationState) {
+        self.animation_state = state;
+        self.sprite.set_animation(self.animations.get(&state).unwrap());
+        self.sprite.play();
+    }
+
+    /// Draw the entity `alpha` of the way between the last two ticks
+    pub fn render(&self, texture: Texture2D, scale: f32, alpha: f32) {
+        let ground = self.previous_position.lerp(self.position, alpha);
+        let height = self.previous_height + (self.height - self.previous_height) * alpha;
+        if height > 0.0 {
+            // Smaller the higher the entity is
+            let radius = Vec2::new(0.5 * self.collision_box.w, 0.5 * self.collision_box.h) / (1.0 + height / 16.0);
+            draw_shadow(ground + self.collision_box.center(), radius, scale);
+        }
+        self.sprite.draw_sprite(ground - Vec2::new(0.0, height), texture, scale, self.tint);
         
         // Debug collision box
         //draw_rectangle(self.get_collision_box().x * scale, self.get_collision_box().y * scale, self.get_collision_box().w * scale, self.get_collision_box().h * scale, BLUE)
     }
 
-    pub fn apply_direction(&mut self) {
-        if self.direction != Vec2::ZERO {
-            self.velocity = self.max_speed * self.direction;
-        } else {
-            self.velocity *= 0.8;
-        }
+    pub fn apply_direction(&mut self, dt: f32) {
+        self.apply_direction_with_speed(self.max_speed, dt);
     }
 
-    pub fn apply_direction_with_speed(&mut self, speed: f32) {
+    pub fn apply_direction_with_speed(&mut self, speed: f32, dt: f32) {
         if self.direction != Vec2::ZERO {
             self.velocity = speed * self.direction;
         } else {
-            self.velocity *= 0.8;
+            self.velocity *= (-VELOCITY_DAMPING * dt).exp();
         }
     }
 
+    /// Give the hero to the player `player`
+    pub fn set_player(&mut self, player: usize) {
+        self.behaviour = Behaviour::Playable { player };
+        self.tint = PLAYER_TINTS[player % PLAYER_TINTS.len()];
+    }
+
     pub fn take(&mut self, id: u32) {
         self.thing_carried = Some(id);
     }
+    /// Taken by the entity `id`, even caught in the air
     pub fn taken_by(&mut self, id: u32, at_position: f32) {
-        self.behaviour = Behaviour::Transported;
+        self.apply(Transition::Push(Behaviour::Transported));
         self.transporter = Some(id);
         self.position.x = at_position;
+        self.height = 0.0;
+        self.previous_height = 0.0;
     }
     pub fn drop(&mut self) {
         self.thing_carried = None;
@@ -121,34 +218,85 @@ impl Entity {
 
         // Il faut dropper devant le dropper
         self.position.y = y + 10.0;
+        self.height = 0.0;
+        self.previous_height = 0.0;
         self.transporter = None;
-        self.behaviour = Behaviour::FreeWalk;
+        self.apply(Transition::Pop);
     }
 
-    pub fn thrown(&mut self, dir: Vec2, yo: f32, thrower: u32) {
-        self.behaviour = Behaviour::Thrown {
-            dir,
-            yo,
-            h: 12.0,
+    /// Throw the carried entity from `ground_y`, where its thrower stands
+    ///
+    /// `velocity` is on the ground and `vertical_speed` upward, both in
+    /// pixels per second.
+    pub fn thrown(&mut self, velocity: Vec2, vertical_speed: f32, ground_y: f32, thrower: u32) {
+        // Still drawn where it was carried, but now from the ground
+        let height = ground_y - self.position.y;
+        self.position.y = ground_y;
+        self.previous_position.y += height;
+        self.height = height;
+        self.previous_height = height;
+
+        self.apply(Transition::Switch(Behaviour::Thrown {
+            velocity,
+            vertical_speed,
             thrower,
-        };
+        }));
         self.transporter = None;
     }
 
-    pub fn motion(&mut self) {
-        self.position += self.velocity;
-        self.sprite.set_position_to(self.position);
+    /// Go to the next behaviour, keeping the interrupted one to resume it
+    pub fn apply(&mut self, transition: Transition) {
+        match transition {
+            Transition::Stay => {}
+            Transition::Switch(behaviour) => self.behaviour = behaviour,
+            Transition::Push(behaviour) => {
+                if self.behaviour.is_resumable() {
+                    self.suspended.push(self.behaviour);
+                }
+                self.behaviour = behaviour;
+            }
+            Transition::Pop => {
+                self.behaviour = self.suspended.pop().unwrap_or(mind(self.entity_type).root);
+            }
+        }
+    }
+
+    pub fn motion(&mut self, dt: f32) {
+        self.position += self.velocity * dt;
         self.animation_manager();
     }
 
     pub fn animation_manager(&mut self) {
+        // One-shot animations are only interrupted when carried or thrown
+        let mut interrupted = false;
+        if let Some(previous) = self.one_shot_from {
+            match self.behaviour {
+                Behaviour::Transported | Behaviour::Thrown { .. } => {
+                    self.one_shot_from = None;
+                    self.animation_state = previous;
+                    interrupted = true;
+                }
+                _ => return,
+            }
+        }
+
         let current_animation = self.animation_state;
         self.animation_state = match self.behaviour {
-            Behaviour::FreeWalk | Behaviour::Playable | Behaviour::DumbDog | Behaviour::RunAway { .. }=> {
-                if self.direction.x < -0.0 {
+            Behaviour::FreeWalk
+            | Behaviour::Flock { .. }
+            | Behaviour::Playable { .. }
+            | Behaviour::DumbDog
+            | Behaviour::RunAway { .. }
+            | Behaviour::Stunned { .. }
+            | Behaviour::Stalk { .. }
+            | Behaviour::Chase { .. }
+            | Behaviour::Drag { .. } => {
+                // Facing where the entity goes the most
+                let sideways = self.direction.x.abs() >= self.direction.y.abs();
+                if sideways && self.direction.x < -0.0 {
                     AnimationState::WalkLeft
                 }
-                else if self.direction.x > 0.0 {
+                else if sideways && self.direction.x > 0.0 {
                     AnimationState::WalkRight
                 }
                 else if self.direction.y > 0.0 {
@@ -187,22 +335,22 @@ impl Entity {
                     current_animation
                 }
             },
-            Behaviour::Thrown { .. } => {current_animation},
+            Behaviour::Thrown { .. } | Behaviour::KnockedBack { .. } | Behaviour::Static => {current_animation},
         };
 
-        if self.animation_state != current_animation {
-            self.sprite.set_animation(&self.animations.get(&self.animation_state).unwrap());
-            self.sprite.play();
+        if self.animation_state != current_animation || interrupted {
+            self.set_animation_state(self.animation_state);
         }
 
     }
 
     pub fn get_collision_box(&self) -> Rect {
         self.collision_box
-            .offset(self.position + self.velocity)
+            .offset(self.position)
     }
-    pub fn get_collision_box_diff(&self, on_x: bool, on_y: bool) -> Rect {
-        let mut diff_velocity = self.velocity;
+    /// Collision box after moving for `dt` seconds, only on the given axes
+    pub fn get_collision_box_diff(&self, on_x: bool, on_y: bool, dt: f32) -> Rect {
+        let mut diff_velocity = self.velocity * dt;
         if !on_x {diff_velocity.x = 0.0}
         if !on_y {diff_velocity.y = 0.0}
         
@@ -213,7 +361,6 @@ impl Entity {
     pub fn depth_sort(&self) -> u32 {
         match self.behaviour {
             Behaviour::Transported => self.position.y as u32 + 12, // transported item are above the real position
-            Behaviour::Thrown { yo, .. } => {yo as u32},
             _ => self.position.y as u32,
         }
     }
@@ -221,8 +368,8 @@ impl Entity {
 
 fn set_animation(
     entity_type: &EntityType,
-    atlas: &HashMap<String, SpriteLibraryData>,
-) -> HashMap<AnimationState, SpriteLibraryData> {
+    atlas: &Atlas,
+) -> Result<HashMap<AnimationState, Rc<Animation>>, AssetError> {
     let mut animations = HashMap::new();
     let list = match entity_type {
         EntityType::Hero => vec![
@@ -244,6 +391,7 @@ fn set_animation(
             (AnimationState::IdleRight, "sheep_idle_right"),
             (AnimationState::IdleUp,    "sheep_idle_up"),
             (AnimationState::IdleDown,  "sheep_idle_down"),
+            (AnimationState::Bleat,     "sheep_bleat"),
         ],
         EntityType::Wolf => vec![
             (AnimationState::WalkRight, "wolf_walk_right"),
@@ -254,27 +402,62 @@ fn set_animation(
             (AnimationState::IdleRight, "wolf_idle_right"),
             (AnimationState::IdleUp,    "wolf_idle_left"),
             (AnimationState::IdleDown,  "wolf_idle_right"),
-        ]
+        ],
+        EntityType::Tree => vec![
+            (AnimationState::WalkRight, "tree"),
+            (AnimationState::WalkLeft,  "tree"),
+            (AnimationState::WalkUp,    "tree"),
+            (AnimationState::WalkDown,  "tree"),
+            (AnimationState::IdleLeft,  "tree"),
+            (AnimationState::IdleRight, "tree"),
+            (AnimationState::IdleUp,    "tree"),
+            (AnimationState::IdleDown,  "tree"),
+        ],
     };
 
     for anim in list.iter() {
         animations.insert(
             anim.0,
-            atlas.get(anim.1).unwrap().clone(),
+            sprite_library::get_animation(atlas, anim.1)?,
         );
     }
 
-    animations
+    Ok(animations)
+}
+
+fn hero_incubator(hero: &mut Entity) {
+    // A hero can't carry the other player
+    hero.carriable = false;
 }
 
 fn sheep_incubator(sheep: &mut Entity) {
-    sheep.max_speed = 0.5;
-    sheep.behaviour = Behaviour::FreeWalk;
+    sheep.max_speed = 30.0;
+    sheep.behaviour = mind(EntityType::Sheep).root;
 }
 
 fn wolf_incubator(wolf: &mut Entity) {
-    wolf.max_speed = 1.5;
+    wolf.max_speed = 90.0;
     wolf.behaviour = Behaviour::FreeWalk;
     wolf.collision_box = Rect::new(11.0, 10.0, 12.0, 6.0);
     wolf.behaviour = Behaviour::DumbDog;
 }
+
+fn tree_incubator(tree: &mut Entity) {
+    tree.max_speed = 0.0;
+    tree.behaviour = Behaviour::Static;
+    tree.collision_box = Rect::new(4.0, 10.0, 8.0, 6.0);
+    tree.carriable = false;
+}
+
+/// Shadow of an entity in the air, an ellipse on the ground
+fn draw_shadow(center: Vec2, radius: Vec2, scale: f32) {
+    const SIDES: usize = 12;
+    let color = Color::new(0.0, 0.0, 0.0, 0.3);
+    let point = |i: usize| {
+        let angle = i as f32 * std::f32::consts::TAU / SIDES as f32;
+        (center + radius * Vec2::new(angle.cos(), angle.sin())) * scale
+    };
+    for i in 0..SIDES {
+        draw_triangle(center * scale, point(i), point(i + 1), color);
+    }
+}
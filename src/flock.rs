@@ -0,0 +1,164 @@
+use macroquad::math::Vec2;
+use serde::Deserialize;
+
+use crate::entities::{Entity, EntityType};
+use crate::puppet_master::Behaviour;
+
+/// How much each urge of a flocking sheep counts, and how far it reaches
+///
+/// Distances are in pixels, between the entities' positions. A level sets
+/// them with fields named `Flock` followed by their name, like
+/// `FlockSeparation` or `FlockNeighbourRadius`.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "PascalCase")]
+pub struct FlockWeights {
+    /// Sheep closer than this are neighbours
+    pub neighbour_radius: f32,
+    /// Keep away from the neighbours closer than `separation_radius`
+    pub separation: f32,
+    pub separation_radius: f32,
+    /// Go the same way as the neighbours
+    pub alignment: f32,
+    /// Go towards the middle of the neighbours
+    pub cohesion: f32,
+    /// Go towards a bleating sheep closer than `call_radius`
+    pub call: f32,
+    pub call_radius: f32,
+    /// Keep away from the wolves closer than `wolf_radius`
+    pub wolf_avoidance: f32,
+    pub wolf_radius: f32,
+    /// Heroes closer than `flight_zone` push the sheep away, the more the
+    /// closer they are
+    pub pressure: f32,
+    pub flight_zone: f32,
+    /// Extra pressure of a hero coming towards the sheep at full speed
+    pub approach: f32,
+    /// Follow its own random wandering
+    pub wander: f32,
+    /// Below this urge, the sheep stays where it is and grazes
+    pub rest: f32,
+}
+
+impl Default for FlockWeights {
+    fn default() -> Self {
+        FlockWeights {
+            neighbour_radius: 48.0,
+            separation: 1.5,
+            separation_radius: 16.0,
+            alignment: 0.4,
+            cohesion: 0.6,
+            call: 1.0,
+            call_radius: 55.0,
+            wolf_avoidance: 2.0,
+            wolf_radius: 96.0,
+            pressure: 1.5,
+            flight_zone: 64.0,
+            approach: 1.0,
+            wander: 0.6,
+            rest: 0.25,
+        }
+    }
+}
+
+/// Where the sheep `ent` wants to go, given its own `wander` direction
+///
+/// The result is at most 1 long, a fraction of the sheep's full speed, and
+/// zero when it would rather graze.
+pub fn steer(ent: &Entity, entities: &[Entity], wander: Vec2, weights: &FlockWeights) -> Vec2 {
+    let mut separation = Vec2::ZERO;
+    let mut heading = Vec2::ZERO;
+    let mut center = Vec2::ZERO;
+    let mut neighbours = 0;
+    let mut urges = wander * weights.wander;
+    let reach = weights.neighbour_radius.max(weights.wolf_radius).max(weights.flight_zone);
+
+    for other in entities.iter() {
+        let offset = ent.position - other.position;
+        // Far entities, and the ones carried or in the air, are out of reach
+        if offset.length_squared() >= reach * reach
+            || other.id == ent.id
+            || matches!(other.behaviour, Behaviour::Transported | Behaviour::Thrown { .. })
+        {
+            continue;
+        }
+        let distance = offset.length();
+        let away = offset.normalize_or_zero();
+        // 1 when on top of each other, 0 at `radius`
+        let closeness = |radius: f32| (1.0 - distance / radius).max(0.0);
+
+        match other.entity_type {
+            EntityType::Sheep if distance < weights.neighbour_radius => {
+                neighbours += 1;
+                heading += other.velocity;
+                center += other.position;
+                separation += away * closeness(weights.separation_radius);
+                if other.has_event("bleat") && distance < weights.call_radius {
+                    urges -= away * weights.call;
+                }
+            }
+            EntityType::Wolf => urges += away * weights.wolf_avoidance * closeness(weights.wolf_radius),
+            EntityType::Hero => urges += herding_pressure(ent, other, weights),
+            _ => {}
+        }
+    }
+
+    urges += separation * weights.separation;
+    if neighbours > 0 {
+        let count = neighbours as f32;
+        // Neighbours grazing don't pull as much as walking ones, and nothing
+        // pulls a sheep that can't walk
+        if ent.max_speed > 0.0 {
+            urges += (heading / (count * ent.max_speed)).clamp_length_max(1.0) * weights.alignment;
+        }
+        urges += ((center / count - ent.position) / weights.neighbour_radius) * weights.cohesion;
+    }
+
+    if urges.length() < weights.rest {
+        Vec2::ZERO
+    } else {
+        urges.clamp_length_max(1.0)
+    }
+}
+
+/// Push of the hero `hero` on the sheep `ent`, away from the hero
+///
+/// Nothing outside of the flight zone, then growing quadratically up to
+/// `pressure` as the hero gets closer, more if the hero walks towards the
+/// sheep.
+pub fn herding_pressure(ent: &Entity, hero: &Entity, weights: &FlockWeights) -> Vec2 {
+    let offset = ent.position - hero.position;
+    let distance = offset.length();
+    if distance >= weights.flight_zone {
+        return Vec2::ZERO;
+    }
+    let away = offset.normalize_or_zero();
+    let falloff = (1.0 - distance / weights.flight_zone).powi(2);
+    let approach = if hero.max_speed > 0.0 {
+        (hero.velocity.dot(away) / hero.max_speed).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    away * weights.pressure * falloff * (1.0 + weights.approach * approach)
+}
+
+#[cfg(test)]
+mod tests {
+    use macroquad::texture::Image;
+
+    use super::*;
+    use crate::sprite_library::read_atlas;
+    use crate::{ATLAS, SPRITESHEET};
+
+    #[test]
+    fn sheep_that_cant_walk_steers_to_a_number() {
+        let image = Image::from_file_with_format(SPRITESHEET, None);
+        let atlas = read_atlas(ATLAS, image.width() as f32, image.height() as f32).unwrap();
+        let mut sheep = Entity::new(100.0, 100.0, EntityType::Sheep, 0, &atlas).unwrap();
+        sheep.max_speed = 0.0;
+        let mut neighbour = Entity::new(120.0, 100.0, EntityType::Sheep, 1, &atlas).unwrap();
+        neighbour.velocity = Vec2::new(0.0, 30.0);
+
+        let dir = steer(&sheep, &[sheep.clone(), neighbour], Vec2::ZERO, &FlockWeights::default());
+        assert!(dir.is_finite(), "{:?}", dir);
+    }
+}
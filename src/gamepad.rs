@@ -0,0 +1,152 @@
+use serde::{Deserialize, Serialize};
+
+/// A button, or a stick pushed in a direction, of a gamepad
+///
+/// Gamepads are only read when the game is built with the `gamepad`
+/// feature, otherwise they are never down.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum PadInput {
+    South,
+    East,
+    North,
+    West,
+    LeftTrigger,
+    RightTrigger,
+    Select,
+    Start,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+    LeftStickUp,
+    LeftStickDown,
+    LeftStickLeft,
+    LeftStickRight,
+}
+
+impl PadInput {
+    pub const ALL: [PadInput; 16] = [
+        PadInput::South,
+        PadInput::East,
+        PadInput::North,
+        PadInput::West,
+        PadInput::LeftTrigger,
+        PadInput::RightTrigger,
+        PadInput::Select,
+        PadInput::Start,
+        PadInput::DPadUp,
+        PadInput::DPadDown,
+        PadInput::DPadLeft,
+        PadInput::DPadRight,
+        PadInput::LeftStickUp,
+        PadInput::LeftStickDown,
+        PadInput::LeftStickLeft,
+        PadInput::LeftStickRight,
+    ];
+}
+
+/// How far a stick must be pushed to count as a direction
+#[cfg(feature = "gamepad")]
+const STICK_DEAD_ZONE: f32 = 0.5;
+
+/// State of all the connected gamepads, by gamepad index
+///
+/// Gamepads get the first free index when they connect, and free it when
+/// unplugged, so a gamepad plugged back goes to the same player.
+pub struct Gamepads {
+    #[cfg(feature = "gamepad")]
+    gilrs: Option<gilrs::Gilrs>,
+    /// Gamepad at each index
+    #[cfg(feature = "gamepad")]
+    slots: Vec<Option<gilrs::GamepadId>>,
+    down: Vec<Vec<PadInput>>,
+    previous: Vec<Vec<PadInput>>,
+}
+
+impl Gamepads {
+    pub fn new() -> Self {
+        Gamepads {
+            #[cfg(feature = "gamepad")]
+            gilrs: gilrs::Gilrs::new()
+                .map_err(|e| eprintln!("gamepads disabled: {}", e))
+                .ok(),
+            #[cfg(feature = "gamepad")]
+            slots: Vec::new(),
+            down: Vec::new(),
+            previous: Vec::new(),
+        }
+    }
+
+    /// Read the gamepads, once per frame
+    pub fn update(&mut self) {
+        self.previous = std::mem::take(&mut self.down);
+
+        #[cfg(feature = "gamepad")]
+        if let Some(gilrs) = self.gilrs.as_mut() {
+            // Events must be pumped for the gamepads' state to change
+            while gilrs.next_event().is_some() {}
+
+            for slot in self.slots.iter_mut() {
+                if slot.is_some_and(|id| gilrs.connected_gamepad(id).is_none()) {
+                    *slot = None;
+                }
+            }
+            for (id, _) in gilrs.gamepads() {
+                if !self.slots.contains(&Some(id)) {
+                    match self.slots.iter_mut().find(|slot| slot.is_none()) {
+                        Some(slot) => *slot = Some(id),
+                        None => self.slots.push(Some(id)),
+                    }
+                }
+            }
+
+            self.down = self
+                .slots
+                .iter()
+                .map(|slot| match slot.and_then(|id| gilrs.connected_gamepad(id)) {
+                    Some(pad) => PadInput::ALL.into_iter().filter(|input| is_down(&pad, *input)).collect(),
+                    None => Vec::new(),
+                })
+                .collect();
+        }
+    }
+
+    pub fn is_down(&self, pad: usize, input: PadInput) -> bool {
+        self.down.get(pad).is_some_and(|down| down.contains(&input))
+    }
+
+    /// An input of the gamepad `pad` pressed since the previous frame, if any
+    pub fn just_pressed(&self, pad: usize) -> Option<PadInput> {
+        let previous = self.previous.get(pad);
+        self.down
+            .get(pad)?
+            .iter()
+            .copied()
+            .find(|input| !previous.is_some_and(|p| p.contains(input)))
+    }
+}
+
+#[cfg(feature = "gamepad")]
+fn is_down(pad: &gilrs::Gamepad, input: PadInput) -> bool {
+    use gilrs::{Axis, Button};
+
+    match input {
+        PadInput::South => pad.is_pressed(Button::South),
+        PadInput::East => pad.is_pressed(Button::East),
+        PadInput::North => pad.is_pressed(Button::North),
+        PadInput::West => pad.is_pressed(Button::West),
+        PadInput::LeftTrigger => pad.is_pressed(Button::LeftTrigger),
+        PadInput::RightTrigger => pad.is_pressed(Button::RightTrigger),
+        PadInput::Select => pad.is_pressed(Button::Select),
+        PadInput::Start => pad.is_pressed(Button::Start),
+        PadInput::DPadUp => pad.is_pressed(Button::DPadUp),
+        PadInput::DPadDown => pad.is_pressed(Button::DPadDown),
+        PadInput::DPadLeft => pad.is_pressed(Button::DPadLeft),
+        PadInput::DPadRight => pad.is_pressed(Button::DPadRight),
+        // Stick y goes up
+        PadInput::LeftStickUp => pad.value(Axis::LeftStickY) > STICK_DEAD_ZONE,
+        PadInput::LeftStickDown => pad.value(Axis::LeftStickY) < -STICK_DEAD_ZONE,
+        PadInput::LeftStickLeft => pad.value(Axis::LeftStickX) < -STICK_DEAD_ZONE,
+        PadInput::LeftStickRight => pad.value(Axis::LeftStickX) > STICK_DEAD_ZONE,
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use macroquad::input::*;
+use macroquad::math::Vec2;
+use serde::{Deserialize, Serialize};
+
+use crate::gamepad::{Gamepads, PadInput};
+use crate::sprite_library::{self, AssetError};
+
+/// What the player asks for, for the next simulation tick
+#[derive(Clone, Copy, Default)]
+pub struct PlayerInput {
+    pub direction: Vec2,
+    /// Take something, or drop what is carried
+    pub grab: bool,
+    /// Throw what is carried where the player is going
+    pub throw: bool,
+}
+
+impl PlayerInput {
+    /// Forget the one-shot actions once a tick has seen them
+    pub fn consume(&mut self) {
+        self.grab = false;
+        self.throw = false;
+    }
+}
+
+/// What the player can do, whatever the device used
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    Grab,
+    Throw,
+}
+
+impl Action {
+    pub const ALL: [Action; 6] = [
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::Grab,
+        Action::Throw,
+    ];
+}
+
+/// A physical input: a key, or a button or stick direction of the player's gamepad
+///
+/// Written `Space`, `W`... for keys and `Pad:South`, `Pad:LeftStickUp`...
+/// for gamepads in the config file.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Binding {
+    Key(KeyCode),
+    Pad(PadInput),
+}
+
+impl Binding {
+    /// Binding for `key`, None for keys reserved by the game like Escape or Tab
+    pub fn key(key: KeyCode) -> Option<Binding> {
+        KEYS.contains(&key).then_some(Binding::Key(key))
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Binding::Key(key) => write!(f, "{:?}", key),
+            Binding::Pad(pad) => write!(f, "Pad:{:?}", pad),
+        }
+    }
+}
+
+impl From<Binding> for String {
+    fn from(binding: Binding) -> String {
+        binding.to_string()
+    }
+}
+
+impl TryFrom<String> for Binding {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Binding, String> {
+        let binding = match name.strip_prefix("Pad:") {
+            Some(pad) => PadInput::ALL.iter().find(|p| format!("{:?}", p) == pad).map(|p| Binding::Pad(*p)),
+            None => KEYS.iter().find(|k| format!("{:?}", k) == name).map(|k| Binding::Key(*k)),
+        };
+        binding.ok_or_else(|| format!("unknown key or button {}", name))
+    }
+}
+
+/// Which physical inputs trigger each action
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Bindings(BTreeMap<Action, Vec<Binding>>);
+
+impl Bindings {
+    /// Bindings of the player `player` out of `players` when nothing is
+    /// configured
+    ///
+    /// The first player uses the arrows, the second one WASD, and every
+    /// player has the gamepad of the same index. Alone, the player has both
+    /// sides of the keyboard.
+    pub fn default_for(player: usize, players: usize) -> Self {
+        use Binding::*;
+        // Space grabs and throws, like before the actions were split
+        let arrows = [KeyCode::Left, KeyCode::Right, KeyCode::Up, KeyCode::Down, KeyCode::Space, KeyCode::Space];
+        let wasd = [KeyCode::A, KeyCode::D, KeyCode::W, KeyCode::S, KeyCode::E, KeyCode::E];
+        let key_sets = match player {
+            0 if players == 1 => vec![arrows, wasd],
+            0 => vec![arrows],
+            1 => vec![wasd],
+            _ => Vec::new(),
+        };
+        let mut bindings = Bindings(BTreeMap::from(pad_bindings()));
+        // Keys before the pad, the first set first
+        for keys in key_sets.iter().rev() {
+            for (action, key) in Action::ALL.iter().zip(keys) {
+                bindings.0.entry(*action).or_default().insert(0, Key(*key));
+            }
+        }
+        bindings
+    }
+
+    /// Read the bindings of every player saved at `path`
+    ///
+    /// Players missing from the file, or all of them if there is no such
+    /// file, get the default bindings. A file saved with a single map, before
+    /// each player had their own, holds the bindings of the first player.
+    pub fn load(path: &Path, players: usize) -> Result<Vec<Bindings>, AssetError> {
+        let mut bindings = if path.exists() {
+            match sprite_library::read_json(path)? {
+                SavedBindings::Players(bindings) => bindings,
+                SavedBindings::Single(bindings) => vec![bindings],
+            }
+        } else {
+            Vec::new()
+        };
+        for player in bindings.len()..players {
+            bindings.push(Bindings::default_for(player, players));
+        }
+        Ok(bindings)
+    }
+
+    pub fn save(bindings: &[Bindings], path: &Path) -> std::io::Result<()> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(BufWriter::new(file), bindings).map_err(|e| e.into())
+    }
+
+    pub fn get(&self, action: Action) -> &[Binding] {
+        self.0.get(&action).map(|b| b.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn add(&mut self, action: Action, binding: Binding) {
+        let bindings = self.0.entry(action).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    pub fn clear(&mut self, action: Action) {
+        self.0.remove(&action);
+    }
+}
+
+/// Content of a bindings file, of every player or of a single one
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SavedBindings {
+    Players(Vec<Bindings>),
+    Single(Bindings),
+}
+
+fn pad_bindings() -> [(Action, Vec<Binding>); 6] {
+    use Binding::Pad;
+    [
+        (Action::MoveLeft, vec![Pad(PadInput::DPadLeft), Pad(PadInput::LeftStickLeft)]),
+        (Action::MoveRight, vec![Pad(PadInput::DPadRight), Pad(PadInput::LeftStickRight)]),
+        (Action::MoveUp, vec![Pad(PadInput::DPadUp), Pad(PadInput::LeftStickUp)]),
+        (Action::MoveDown, vec![Pad(PadInput::DPadDown), Pad(PadInput::LeftStickDown)]),
+        (Action::Grab, vec![Pad(PadInput::South)]),
+        (Action::Throw, vec![Pad(PadInput::West)]),
+    ]
+}
+
+/// Turn the physical inputs of each frame into a `PlayerInput` per player
+///
+/// The player `n` uses their own bindings and the gamepad `n`.
+pub struct InputMap {
+    pub bindings: Vec<Bindings>,
+    pub gamepads: Gamepads,
+    /// Actions held by each player during the previous frame, to only grab
+    /// or throw once per press
+    held: Vec<Vec<Action>>,
+}
+
+impl InputMap {
+    pub fn new(bindings: Vec<Bindings>) -> Self {
+        InputMap {
+            held: vec![Vec::new(); bindings.len()],
+            bindings,
+            gamepads: Gamepads::new(),
+        }
+    }
+
+    fn is_down(&self, player: usize, action: Action) -> bool {
+        self.bindings[player].get(action).iter().any(|binding| match binding {
+            Binding::Key(key) => is_key_down(*key),
+            Binding::Pad(pad) => self.gamepads.is_down(player, *pad),
+        })
+    }
+
+    /// Read the devices, once per rendered frame
+    ///
+    /// A grab or a throw is kept until a tick consumes it, so it isn't lost
+    /// when no tick runs during a frame.
+    pub fn poll(&mut self, inputs: &mut [PlayerInput]) {
+        self.gamepads.update();
+        for (player, input) in inputs.iter_mut().enumerate().take(self.bindings.len()) {
+            let held: Vec<Action> = Action::ALL.iter().copied().filter(|a| self.is_down(player, *a)).collect();
+            let axis = |negative, positive| match (held.contains(&negative), held.contains(&positive)) {
+                (true, false) => -1.0,
+                (false, true) => 1.0,
+                _ => 0.0,
+            };
+
+            input.direction = Vec2::new(axis(Action::MoveLeft, Action::MoveRight), axis(Action::MoveUp, Action::MoveDown));
+            input.grab |= held.contains(&Action::Grab) && !self.held[player].contains(&Action::Grab);
+            input.throw |= held.contains(&Action::Throw) && !self.held[player].contains(&Action::Throw);
+            self.held[player] = held;
+        }
+    }
+}
+
+/// Keys that can be bound to an action
+const KEYS: &[KeyCode] = {
+    use KeyCode::*;
+    &[
+        Space, Apostrophe, Comma, Minus, Period, Slash, Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9,
+        Semicolon, Equal, A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+        LeftBracket, Backslash, RightBracket, GraveAccent, Enter, Backspace, Insert, Delete, Right, Left, Down,
+        Up, PageUp, PageDown, Home, End, CapsLock, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12, Kp0, Kp1, Kp2,
+        Kp3, Kp4, Kp5, Kp6, Kp7, Kp8, Kp9, KpDecimal, KpDivide, KpMultiply, KpSubtract, KpAdd, KpEnter, LeftShift,
+        LeftControl, LeftAlt, RightShift, RightControl, RightAlt,
+    ]
+};
@@ -0,0 +1,129 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::level::LevelError;
+
+/// Raw LDtk project, as saved by the editor
+///
+/// Only the parts of the format used by the game are described here,
+/// everything else in the file is ignored by serde.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LdtkProject {
+    pub defs: Definitions,
+    pub levels: Vec<LdtkLevel>,
+}
+
+#[derive(Deserialize)]
+pub struct Definitions {
+    pub tilesets: Vec<TilesetDef>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TilesetDef {
+    pub uid: i32,
+    pub tile_grid_size: i32,
+    /// Image of the tileset, relative to the project, None for the ones
+    /// embedded in LDtk
+    pub rel_path: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LdtkLevel {
+    pub identifier: String,
+    pub px_wid: i32,
+    pub px_hei: i32,
+    pub field_instances: Vec<FieldInstance>,
+    pub layer_instances: Option<Vec<LayerInstance>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LayerInstance {
+    #[serde(rename = "__identifier")]
+    pub identifier: String,
+    #[serde(rename = "__type")]
+    pub layer_type: String,
+    #[serde(rename = "__cWid")]
+    pub c_wid: i32,
+    #[serde(rename = "__cHei")]
+    pub c_hei: i32,
+    #[serde(rename = "__gridSize")]
+    pub grid_size: i32,
+    #[serde(rename = "__tilesetDefUid")]
+    pub tileset_def_uid: Option<i32>,
+    pub int_grid_csv: Vec<i32>,
+    pub grid_tiles: Vec<TileInstance>,
+    pub auto_layer_tiles: Vec<TileInstance>,
+    pub entity_instances: Vec<EntityInstance>,
+}
+
+#[derive(Deserialize)]
+pub struct TileInstance {
+    /// Position of the tile in the layer, in pixels
+    pub px: [i32; 2],
+    /// Position of the tile in the tileset, in pixels
+    pub src: [i32; 2],
+    /// Flip bits: 1 for x, 2 for y
+    pub f: i32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EntityInstance {
+    #[serde(rename = "__identifier")]
+    pub identifier: String,
+    #[serde(rename = "__pivot")]
+    pub pivot: [f32; 2],
+    pub px: [i32; 2],
+    pub width: i32,
+    pub height: i32,
+    pub field_instances: Vec<FieldInstance>,
+}
+
+#[derive(Deserialize)]
+pub struct FieldInstance {
+    #[serde(rename = "__identifier")]
+    pub identifier: String,
+    #[serde(rename = "__value")]
+    pub value: serde_json::Value,
+}
+
+impl LdtkProject {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<LdtkProject, LevelError> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|source| LevelError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        serde_json::from_reader(BufReader::new(file)).map_err(|e| LevelError::Parse {
+            path: path.to_path_buf(),
+            line: e.line(),
+            column: e.column(),
+            message: e.to_string(),
+        })
+    }
+
+    pub fn level(&self, identifier: &str) -> Option<&LdtkLevel> {
+        self.levels.iter().find(|l| l.identifier == identifier)
+    }
+
+    pub fn tileset(&self, uid: i32) -> Option<&TilesetDef> {
+        self.defs.tilesets.iter().find(|t| t.uid == uid)
+    }
+}
+
+impl LdtkLevel {
+    /// Value of the level's custom field named `identifier`, if any
+    pub fn field(&self, identifier: &str) -> Option<&serde_json::Value> {
+        self.field_instances
+            .iter()
+            .find(|f| f.identifier == identifier)
+            .map(|f| &f.value)
+    }
+}
@@ -1,119 +1,374 @@
-use std::path::Path;
-use std::fs::{File, self};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
 
-use macroquad::shapes::draw_rectangle;
 use macroquad::texture::{Texture2D, draw_texture_ex};
-use macroquad::color::{WHITE, RED};
+use macroquad::color::WHITE;
 use macroquad::math::{Rect, Vec2};
 
 use macroquad::texture::DrawTextureParams;
-use serde::Deserialize;
 
+use crate::ldtk::{LayerInstance, LdtkLevel, LdtkProject};
 
+/// Everything that can go wrong while loading a level
+#[derive(Debug)]
+pub enum LevelError {
+    /// The project file can't be read
+    Io { path: PathBuf, source: std::io::Error },
+    /// The project file isn't a valid LDtk project
+    Parse { path: PathBuf, line: usize, column: usize, message: String },
+    UnknownLevel { identifier: String },
+    /// The level is saved in its own file, which is not supported
+    ExternalLevel { identifier: String },
+    MissingLayer { level: String, layer: String },
+    /// An IntGrid layer doesn't have one value per cell of the level
+    GridSize { level: String, layer: String, cell_w: usize, cell_h: usize, values: usize },
+    /// An IntGrid cell holds a value that doesn't fit in the grid
+    InvalidCell { level: String, layer: String, x: usize, y: usize, value: i32 },
+    /// The level holds an entity the game doesn't know
+    UnknownEntity { level: String, entity: String },
+    /// A custom field of the level, or of one of its entities, has a value
+    /// the game can't use
+    InvalidField { level: String, entity: Option<String>, field: String, message: String },
+    /// Not enough free cells in an entity's area for all its copies
+    NoFreePlace { level: String, entity: String, count: i64 },
+    /// A tile layer uses another image than the spritesheet
+    UnsupportedTileset { level: String, layer: String, path: Option<String> },
+}
 
-#[derive(Deserialize)]
-struct SimplifiedLdtk{
-    width: i32,
-    height: i32,
-    //layers: Vec<String>,
-    //entities: Vec<LdtkEntity>
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LevelError::Io { path, source } => write!(f, "can't read {}: {}", path.display(), source),
+            LevelError::Parse { path, line, column, message } => {
+                write!(f, "{}:{}:{}: invalid LDtk project: {}", path.display(), line, column, message)
+            }
+            LevelError::UnknownLevel { identifier } => write!(f, "no level named {} in the LDtk project", identifier),
+            LevelError::ExternalLevel { identifier } => {
+                write!(f, "level {} is saved in a separate file, which is not supported", identifier)
+            }
+            LevelError::MissingLayer { level, layer } => write!(f, "level {} has no {} layer", level, layer),
+            LevelError::GridSize { level, layer, cell_w, cell_h, values } => write!(
+                f,
+                "level {}, layer {}: expected {}x{} cells, found {} values",
+                level, layer, cell_w, cell_h, values
+            ),
+            LevelError::InvalidCell { level, layer, x, y, value } => {
+                write!(f, "level {}, layer {}: invalid value {} in cell ({}, {})", level, layer, value, x, y)
+            }
+            LevelError::UnknownEntity { level, entity } => write!(f, "level {}: unknown entity {}", level, entity),
+            LevelError::InvalidField { level, entity: Some(entity), field, message } => {
+                write!(f, "level {}, entity {}: invalid field {}: {}", level, entity, field, message)
+            }
+            LevelError::InvalidField { level, entity: None, field, message } => {
+                write!(f, "level {}: invalid field {}: {}", level, field, message)
+            }
+            LevelError::NoFreePlace { level, entity, count } => {
+                write!(f, "level {}: not enough free cells for {} {} entities", level, count, entity)
+            }
+            LevelError::UnsupportedTileset { level, layer, path } => write!(
+                f,
+                "level {}, layer {}: tileset {} is not supported, only {} is",
+                level,
+                layer,
+                path.as_deref().unwrap_or("embedded in LDtk"),
+                TILESET
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LevelError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LevelError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
 }
 
+/// The collision box of a cell is moved down a bit, so entities can overlap
+/// the wall above them like it's behind them
+const COLLISION_Y_OFFSET: f32 = 5.0;
 
-pub struct CBox{
-    rect: Rect,
-    box_type: u8
+/// The only tileset image tiles are drawn from, relative to the project
+const TILESET: &str = "spritesheet.png";
+
+/// A ground tile, taken from the level's tileset
+struct Tile {
+    position: Vec2,
+    source: Rect,
+    flip_x: bool,
+    flip_y: bool,
+}
+
+/// An entity placed in the level with LDtk
+pub struct LevelEntity {
+    pub identifier: String,
+    pub position: Vec2,
+    pub width: f32,
+    pub height: f32,
+    pub fields: HashMap<String, serde_json::Value>,
 }
 
+impl LevelEntity {
+    pub fn area(&self) -> Rect {
+        Rect::new(self.position.x, self.position.y, self.width, self.height)
+    }
+
+    pub fn field_int(&self, identifier: &str) -> Option<i64> {
+        self.fields.get(identifier).and_then(|v| v.as_i64())
+    }
+
+    pub fn field_float(&self, identifier: &str) -> Option<f64> {
+        self.fields.get(identifier).and_then(|v| v.as_f64())
+    }
+
+    pub fn field_str(&self, identifier: &str) -> Option<&str> {
+        self.fields.get(identifier).and_then(|v| v.as_str())
+    }
+}
 
 
 pub struct Level  {
+    /// Name of the level in the LDtk project
+    pub identifier: String,
     pub cell_w: usize,
     pub cell_h: usize,
+    /// Size of a cell of the collision grid, in pixels
+    pub grid_size: f32,
     pub width: f32,
     pub height: f32,
     pub collision_grid: Vec<u8>,
-    pub collision_boxes: Vec<CBox>,
+    pub entities: Vec<LevelEntity>,
+    tiles: Vec<Tile>,
 }
 
 
 impl Level {
-    pub fn new() -> Level {
-        let data_path = Path::new(&"./assets/sheep/simplified/Level_0/data.json");
-        let data_file = File::open(data_path).expect("erreur de lecture - ldtk");
-        let data: SimplifiedLdtk = serde_json::from_reader(data_file).unwrap();
-
-        let collision_raw = fs::read_to_string("./assets/sheep/simplified/Level_0/Collision.csv").expect("erreur lecture cvs file");
-        let collision_grid = extract_cvs(collision_raw);
-
-        let mut collision_boxes = Vec::new();
-        for (index, value) in collision_grid.iter().enumerate() {
-            if *value != 0 {
-                collision_boxes.push(CBox{
-                    rect: Rect { x: 16.0 * (index % (data.width / 16) as usize) as f32, y: 16.0 * (index / (data.width / 16) as usize) as f32 + 5.0, w: 16.0, h: 16.0 },
-                    box_type: *value,
+    /// Build the level named `identifier` from a LDtk project
+    ///
+    /// The collision grid comes from the `Collision` IntGrid layer, the ground
+    /// from every tile layer (drawn from the bottom one to the top one), which
+    /// must all use the spritesheet as their tileset.
+    pub fn from_project(project: &LdtkProject, identifier: &str) -> Result<Level, LevelError> {
+        let data = project.level(identifier).ok_or_else(|| LevelError::UnknownLevel {
+            identifier: identifier.to_string(),
+        })?;
+        let layers = data.layer_instances.as_ref().ok_or_else(|| LevelError::ExternalLevel {
+            identifier: identifier.to_string(),
+        })?;
+
+        let collision_layer = layers
+            .iter()
+            .find(|l| l.identifier == "Collision")
+            .ok_or_else(|| LevelError::MissingLayer {
+                level: identifier.to_string(),
+                layer: "Collision".to_string(),
+            })?;
+        let cell_w = collision_layer.c_wid as usize;
+        let cell_h = collision_layer.c_hei as usize;
+        let grid_size = collision_layer.grid_size as f32;
+        let collision_grid = read_int_grid(data, collision_layer)?;
+
+        // LDtk stores the layers from the top one to the bottom one
+        let mut tiles = Vec::new();
+        for layer in layers.iter().rev() {
+            let tile_size = match layer.tileset_def_uid.and_then(|uid| project.tileset(uid)) {
+                // Tiles are drawn from the spritesheet's texture
+                Some(tileset) if tileset.rel_path.as_deref() == Some(TILESET) => tileset.tile_grid_size as f32,
+                Some(tileset) => {
+                    return Err(LevelError::UnsupportedTileset {
+                        level: identifier.to_string(),
+                        layer: layer.identifier.clone(),
+                        path: tileset.rel_path.clone(),
+                    })
+                }
+                None => continue,
+            };
+            for tile in layer.grid_tiles.iter().chain(layer.auto_layer_tiles.iter()) {
+                tiles.push(Tile {
+                    position: Vec2::new(tile.px[0] as f32, tile.px[1] as f32),
+                    source: Rect::new(tile.src[0] as f32, tile.src[1] as f32, tile_size, tile_size),
+                    flip_x: tile.f & 1 != 0,
+                    flip_y: tile.f & 2 != 0,
                 });
+            }
+        }
 
+        let mut entities = Vec::new();
+        for layer in layers.iter().filter(|l| l.layer_type == "Entities") {
+            for instance in layer.entity_instances.iter() {
+                let width = instance.width as f32;
+                let height = instance.height as f32;
+                entities.push(LevelEntity {
+                    identifier: instance.identifier.clone(),
+                    // px is the pivot position, we want the top left corner
+                    position: Vec2::new(
+                        instance.px[0] as f32 - instance.pivot[0] * width,
+                        instance.px[1] as f32 - instance.pivot[1] * height,
+                    ),
+                    width,
+                    height,
+                    fields: instance
+                        .field_instances
+                        .iter()
+                        .map(|f| (f.identifier.clone(), f.value.clone()))
+                        .collect(),
+                });
             }
         }
 
+        Ok(Level {
+            identifier: identifier.to_string(),
+            cell_w,
+            cell_h,
+            grid_size,
+            width: data.px_wid as f32,
+            height: data.px_hei as f32,
+            collision_grid,
+            entities,
+            tiles,
+        })
+    }
+
+    /// Level made of a collision grid only, without name, tiles nor entities
+    pub fn from_grid(cell_w: usize, cell_h: usize, grid_size: f32, collision_grid: Vec<u8>) -> Level {
+        assert_eq!(collision_grid.len(), cell_w * cell_h);
         Level {
-            cell_w: (data.width / 16) as usize,
-            cell_h: (data.height / 16) as usize,
-            width: data.width as f32,
-            height: data.height as f32,
+            identifier: String::new(),
+            cell_w,
+            cell_h,
+            grid_size,
+            width: cell_w as f32 * grid_size,
+            height: cell_h as f32 * grid_size,
             collision_grid,
-            collision_boxes
+            entities: Vec::new(),
+            tiles: Vec::new(),
         }
     }
 
+    /// Collision box of the cell (x, y)
+    pub fn cell_box(&self, x: usize, y: usize) -> Rect {
+        Rect::new(
+            x as f32 * self.grid_size,
+            y as f32 * self.grid_size + COLLISION_Y_OFFSET,
+            self.grid_size,
+            self.grid_size,
+        )
+    }
+
+    /// Index in the collision grid of the cell under `point`, the closest one
+    /// when out of the level
+    pub fn cell_index(&self, point: Vec2) -> usize {
+        let cell = |v: f32, cells: usize| ((v / self.grid_size).floor().max(0.0) as usize).min(cells - 1);
+        cell(point.x, self.cell_w) + cell(point.y - COLLISION_Y_OFFSET, self.cell_h) * self.cell_w
+    }
+
+    /// True if `rect` touches the collision box of a cell holding `value`
     pub fn cbox_overlaps_with_value(&self, value: u8, rect: Rect) -> bool {
-        let mut flag = false;
-        for cbox in self.collision_boxes.iter() {
-            if cbox.box_type == value &&rect.overlaps(&cbox.rect) {
-                flag = true;
-                break;
+        self.cboxes_with_value(value, rect).next().is_some()
+    }
+
+    /// Collision boxes of the cells holding `value` that touch `rect`
+    ///
+    /// Only the cells under `rect` are looked at, so the cost doesn't depend
+    /// on the size of the level.
+    pub fn cboxes_with_value(&self, value: u8, rect: Rect) -> impl Iterator<Item = Rect> + '_ {
+        let g = self.grid_size;
+        // Boxes touching the rectangle's edges count, like `Rect::overlaps`
+        let first = |start: f32, offset: f32| ((start - offset - g) / g).ceil().max(0.0) as usize;
+        let last = |end: f32, offset: f32, cells: usize| {
+            let cell = ((end - offset) / g).floor();
+            if cell < 0.0 || cells == 0 {
+                None
+            } else {
+                Some((cell as usize).min(cells - 1))
             }
-        }
-        
-        flag
+        };
+
+        // Empty ranges when the rectangle is out of the level
+        let (x_range, y_range) = match (
+            last(rect.x + rect.w, 0.0, self.cell_w),
+            last(rect.y + rect.h, COLLISION_Y_OFFSET, self.cell_h),
+        ) {
+            (Some(x_last), Some(y_last)) => (first(rect.x, 0.0)..x_last + 1, first(rect.y, COLLISION_Y_OFFSET)..y_last + 1),
+            _ => (0..0, 0..0),
+        };
+        y_range
+            .flat_map(move |y| x_range.clone().map(move |x| (x, y)))
+            .filter(move |(x, y)| self.collision_grid[x + self.cell_w * y] == value)
+            .map(|(x, y)| self.cell_box(x, y))
+            .filter(move |cbox| rect.overlaps(cbox))
     }
 
-    pub fn render(&mut self, texture: Texture2D, scale: f32) {
-        let params = DrawTextureParams {
-            dest_size: Some(Vec2::new(self.width * scale, self.height * scale)),
-            source: Some(Rect::new(0.0, 0.0, self.width, self.height)),
-            rotation: 0.0,
-            flip_x: false,
-            flip_y: false,
-            pivot: None };
-        draw_texture_ex(texture, 0.0, 0.0, WHITE, params);
+    pub fn render(&self, texture: Texture2D, scale: f32) {
+        for tile in self.tiles.iter() {
+            let params = DrawTextureParams {
+                dest_size: Some(Vec2::new(tile.source.w * scale, tile.source.h * scale)),
+                source: Some(tile.source),
+                rotation: 0.0,
+                flip_x: tile.flip_x,
+                flip_y: tile.flip_y,
+                pivot: None };
+            draw_texture_ex(texture, tile.position.x * scale, tile.position.y * scale, WHITE, params);
+        }
 
         // debug collision box
-        //for b in self.collision_boxes.iter() {
-        //    let mut color = WHITE;
-        //    if b.box_type == 2 {color = RED}
-        //    draw_rectangle(b.rect.x * 3.0, b.rect.y * 3.0, b.rect.w * 3.0, b.rect.h * 3.0, color);
+        //for (i, value) in self.collision_grid.iter().enumerate().filter(|(_, v)| **v != 0) {
+        //    let b = self.cell_box(i % self.cell_w, i / self.cell_w);
+        //    let color = if *value == 2 { RED } else { WHITE };
+        //    draw_rectangle(b.x * 3.0, b.y * 3.0, b.w * 3.0, b.h * 3.0, color);
         //}
     }
-    
 
-    pub fn get_int_at(&self, x: usize, y: usize) -> u8 {
-        self.collision_grid[x + self.cell_w * y]
+
+    /// IntGrid value of the cell (x, y), None outside the level
+    pub fn get_int_at(&self, x: usize, y: usize) -> Option<u8> {
+        if x < self.cell_w && y < self.cell_h {
+            Some(self.collision_grid[x + self.cell_w * y])
+        } else {
+            None
+        }
     }
 
 }
 
+/// Read the values of an IntGrid layer, row by row
+///
+/// The layer must cover the whole level with one value per cell, and each
+/// value must fit in a u8.
+fn read_int_grid(level: &LdtkLevel, layer: &LayerInstance) -> Result<Vec<u8>, LevelError> {
+    let cell_w = layer.c_wid.max(0) as usize;
+    let cell_h = layer.c_hei.max(0) as usize;
+    let grid_size = layer.grid_size.max(1);
+    let size_error = || LevelError::GridSize {
+        level: level.identifier.clone(),
+        layer: layer.identifier.clone(),
+        cell_w,
+        cell_h,
+        values: layer.int_grid_csv.len(),
+    };
 
-
-
-/// A basic approch, just for a specific use
-fn extract_cvs(raw_data: String) -> Vec<u8> {
-    let mut output = Vec::new();
-    for i in 0..raw_data.len() {
-        if raw_data.chars().nth(i).unwrap() as u8 <= 57 && raw_data.chars().nth(i).unwrap() as u8 >= 48 {
-            output.push(raw_data.chars().nth(i).unwrap() as u8 - 48);
-        }
+    // LDtk rounds the number of cells up when the level isn't a multiple of the grid
+    let covers_level = layer.c_wid == (level.px_wid + grid_size - 1) / grid_size
+        && layer.c_hei == (level.px_hei + grid_size - 1) / grid_size;
+    if !covers_level || layer.int_grid_csv.len() != cell_w * cell_h {
+        return Err(size_error());
     }
-    output
-}
\ No newline at end of file
+
+    layer
+        .int_grid_csv
+        .iter()
+        .enumerate()
+        .map(|(index, value)| {
+            u8::try_from(*value).map_err(|_| LevelError::InvalidCell {
+                level: level.identifier.clone(),
+                layer: layer.identifier.clone(),
+                x: index % cell_w,
+                y: index / cell_w,
+                value: *value,
+            })
+        })
+        .collect()
+}
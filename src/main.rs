@@ -1,142 +1,452 @@
 use std::time::{SystemTime, UNIX_EPOCH};
-use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
 use macroquad::prelude::*;
-use macroquad::rand::{gen_range, srand};
 
-use sprite_library::*;
 mod sprite_library;
 
 mod puppet_master;
 mod sprite;
+mod aseprite;
 
-use entities::{Entity, EntityType};
+mod broad_phase;
+mod sweep;
 mod entities;
-
-use level::Level;
+mod flock;
 mod level;
+mod mind;
+mod pathfinding;
+mod ldtk;
+mod campaign;
+mod spawner;
+mod rng;
+
+use entities::Entity;
+use input::{Bindings, InputMap, PlayerInput};
+mod input;
+mod gamepad;
+
+use options_menu::OptionsMenu;
+mod options_menu;
+
+use simulation::Simulation;
+mod simulation;
+
+use replay::{Recording, Replay};
+mod replay;
+
+mod bench;
 
+const LDTK_PROJECT: &str = "./assets/sheep.ldtk";
+const ATLAS: &str = "./assets/atlas.json";
+const SPRITESHEET: &[u8] = include_bytes!("../assets/spritesheet.png");
+const DEFAULT_LEVEL: &str = "Level_0";
+/// Keys and buttons of each action, changed in the options menu
+const INPUT_CONFIG: &str = "./input.json";
+
+/// Simulation ticks per second, whatever the frame rate
+const TICK_RATE: f32 = 60.0;
+const FIXED_DT: f32 = 1.0 / TICK_RATE;
+/// Longest frame time simulated, so a hiccup doesn't freeze the game catching up
+const MAX_FRAME_TIME: f32 = 0.25;
+
+/// The simulation, and what is needed to show it in a window
 struct Game {
-    //id_counter: u32,
-    level: Level,
+    simulation: Simulation,
     texture: Texture2D,
-    ground_texture: Texture2D,
-    sheep_in: i32,
-    //atlas: HashMap<String, SpriteLibraryData>,
     scale: f32,
-
-    entities: Vec<Entity>,
 }
 
 impl Game {
-    fn new() -> Self {
-        srand(
-            SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_millis() as u64,
-        );
-        let texture = Texture2D::from_file_with_format(include_bytes!("../assets/spritesheet.png"), None);
+    fn new(level_identifier: &str, seed: u64, players: usize) -> Result<Self, Box<dyn Error>> {
+        let image = Image::from_file_with_format(SPRITESHEET, None);
+        let texture = Texture2D::from_image(&image);
         texture.set_filter(FilterMode::Nearest);
 
-        let ground_texture = Texture2D::from_file_with_format(include_bytes!("../assets/sheep/simplified/Level_0/Ground.png"), None);
-        ground_texture.set_filter(FilterMode::Nearest);
+        let simulation = Simulation::new(
+            LDTK_PROJECT,
+            ATLAS,
+            level_identifier,
+            texture.width(),
+            texture.height(),
+            seed,
+            players,
+        )?;
+
+        Ok(Self {
+            simulation,
+            texture,
+            scale: 3.0,
+        })
+    }
+
+    /// Draw the game `alpha` of the way between the last two ticks
+    fn render(&self, alpha: f32) {
+        let sim = &self.simulation;
+
+        // Draw ground, the level's tileset is the spritesheet
+        sim.level.render(self.texture, self.scale);
+        
+        // Sort all element before displaying (depth sorting), without
+        // touching the simulation's order which must not depend on rendering
+        let mut sorted: Vec<&Entity> = sim.entities.iter().collect();
+        sorted.sort_by_key(|k| k.depth_sort());
+
+        // ... and draw all the entities
+        for ent in sorted {
+            ent.render(self.texture, self.scale, alpha);
+        }
+        draw_text(
+            &format!(
+                "{} / {}  lost {}  score {}",
+                sim.sheep_in,
+                sim.campaign().current().sheep_goal,
+                sim.sheep_lost,
+                sim.score
+            ),
+            30.0,
+            30.0,
+            24.0,
+            RED,
+        );
 
-        let level = Level::new();
+        if sim.campaign_over {
+            draw_text("All your sheep are safe!", 30.0, 60.0, 24.0, RED);
+        } else if sim.level_lost {
+            draw_text("The wolves took too many sheep...", 30.0, 60.0, 24.0, RED);
+        } else if sim.transition.is_some() {
+            draw_text("Well done!", 30.0, 60.0, 24.0, RED);
+        }
+    }
+}
 
-        let mut id_counter = 0;
+/// Value following the flag `name` on the command line
+fn arg_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|a| a == name)
+        .and_then(|i| args.get(i + 1))
+        .map(|s| s.as_str())
+}
 
+/// What the game is asked to do from the command line
+struct Options {
+    level_identifier: String,
+    seed: u64,
+    players: usize,
+    headless: bool,
+    ticks: Option<u64>,
+    record: Option<PathBuf>,
+    replay: Option<Replay>,
+}
 
-        let atlas: HashMap<String, SpriteLibraryData> = sprite_library::read_atlas();
+impl Options {
+    fn parse(args: &[String]) -> Result<Options, Box<dyn Error>> {
+        // The campaign can be started at any level with `--level <identifier>`
+        let mut level_identifier = arg_value(args, "--level").unwrap_or(DEFAULT_LEVEL).to_string();
 
-        let mut entities = Vec::new();
-        let hero = Entity::new(10.0, 0.0, EntityType::Hero, id_counter, &atlas);
+        // A run can be replayed with `--seed <n>`, the seed is random otherwise
+        let mut seed = match arg_value(args, "--seed") {
+            Some(seed) => seed.parse().map_err(|_| "--seed needs a positive integer")?,
+            None => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64,
+        };
 
-        entities.push(hero);
+        // `--players <n>` for local co-op, each player has a hero and a gamepad
+        let mut players = match arg_value(args, "--players") {
+            Some(players) => players.parse().ok().filter(|p| *p > 0).ok_or("--players needs a number above 0")?,
+            None => 1,
+        };
 
-        // create a vec to store all places already taked by a sheep
-        let mut entities_grid: Vec<bool> = vec![true; level.cell_w * level.cell_h];
-        for _i in 0..10 {
-            id_counter += 1;
-            let mut free_place = false;
-            let mut x: usize = 0;
-            let mut y: usize = 0;
+        let ticks = match arg_value(args, "--ticks") {
+            Some(ticks) => Some(ticks.parse().map_err(|_| format!("invalid number of ticks: {}", ticks))?),
+            None => None,
+        };
 
-            // Check if the place is free
-            while !free_place {
-                x = gen_range(0, 26);
-                y = gen_range(0, 15);
-                if level.get_int_at(x, y) == 0 && entities_grid[x + y * level.cell_w] {
-                    free_place = true;
-                }
+        // `--replay <file>` plays a recording back, with its own level, seed and players
+        let replay = match arg_value(args, "--replay") {
+            Some(path) => {
+                let recording = Recording::load(Path::new(path))?;
+                level_identifier = recording.level.clone();
+                seed = recording.seed;
+                players = recording.players;
+                Some(Replay::new(recording))
             }
-            entities_grid[x + y * level.cell_w] = false;
-            let sheep = Entity::new((x * 16) as f32, (y * 16) as f32, EntityType::Sheep, id_counter, &atlas);
+            None => None,
+        };
 
-            entities.push(sheep);
+        Ok(Options {
+            level_identifier,
+            seed,
+            players,
+            // `--headless --ticks <n>` runs the simulation without opening a window
+            headless: args.iter().any(|a| a == "--headless"),
+            ticks,
+            // `--record <file>` saves the inputs of the run when the game is quit
+            record: arg_value(args, "--record").map(PathBuf::from),
+            replay,
+        })
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let options = match Options::parse(&args) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            std::process::exit(1);
         }
-        id_counter += 1;
-        entities.push(Entity::new(10.0, 100.0, EntityType::Wolf, id_counter, &atlas));
+    };
 
-        Self {
-            //id_counter,
-            texture,
-            level,
-            ground_texture,
-            sheep_in: 0,
-            //atlas,
-            scale: 3.0,
-            entities,
+    // `--bench <name>` measures a part of the game, see src/bench.rs
+    if let Some(name) = arg_value(&args, "--bench") {
+        if let Err(error) = bench::run(name) {
+            eprintln!("error: {}", error);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if options.headless {
+        if let Err(error) = run_headless(options) {
+            eprintln!("error: {}", error);
+            std::process::exit(1);
         }
+        return;
+    }
+
+    println!("seed {}", options.seed);
+    macroquad::Window::from_config(window_conf(), run_windowed(options));
+}
+
+/// Run `ticks` simulation ticks with nobody playing, or the ticks of the
+/// replay, then print the final state
+fn run_headless(mut options: Options) -> Result<(), Box<dyn Error>> {
+    let ticks = match (options.ticks, &options.replay) {
+        (Some(ticks), _) => ticks,
+        (None, Some(replay)) => replay.recording.len(),
+        (None, None) => return Err("--headless needs --ticks <n>".into()),
+    };
+
+    // Only the size of the spritesheet is needed, to check the atlas
+    let image = Image::from_file_with_format(SPRITESHEET, None);
+    let mut simulation = Simulation::new(
+        LDTK_PROJECT,
+        ATLAS,
+        &options.level_identifier,
+        image.width() as f32,
+        image.height() as f32,
+        options.seed,
+        options.players,
+    )?;
+
+    if let Some(replay) = options.replay.as_mut() {
+        replay.seek(&mut simulation, ticks, FIXED_DT)?;
+        print_state(&simulation);
+        return match replay.desync.take() {
+            Some(desync) => Err(desync.into()),
+            None => Ok(()),
+        };
+    }
+
+    let inputs = vec![PlayerInput::default(); options.players];
+    let mut recording = Recording::new(options.seed, &options.level_identifier, options.players);
+    for _ in 0..ticks {
+        simulation.update(FIXED_DT, &inputs)?;
+        recording.push(&inputs, &simulation);
+    }
+    if let Some(path) = options.record {
+        recording.save(&path)?;
     }
 
-    //fn id_generator( &mut self) -> u32 {
-    //    self.id_counter += 1;
-    //    self.id_counter
-    //}
+    print_state(&simulation);
+    Ok(())
+}
 
-    fn update(&mut self) {
-        puppet_master::play(&mut self.entities, &self.level);
+fn print_state(simulation: &Simulation) {
+    println!(
+        "level {} after {} ticks ({:.2} s)",
+        simulation.campaign().current().identifier,
+        simulation.ticks,
+        simulation.ticks as f32 * FIXED_DT
+    );
+    println!("seed {}", simulation.seed);
+    println!("checksum {:016x}", simulation.checksum());
+    println!("sheep in: {} / {}", simulation.sheep_in, simulation.campaign().current().sheep_goal);
+    println!("sheep lost: {}", simulation.sheep_lost);
+    println!("score: {}", simulation.score);
+    if simulation.campaign_over {
+        println!("campaign over");
+    }
+    for ent in simulation.entities.iter() {
+        println!(
+            "{:?} #{} at ({:.1}, {:.1}) {:?}",
+            ent.entity_type, ent.id, ent.position.x, ent.position.y, ent.behaviour
+        );
+    }
+}
 
-        // Need to check how many sheep are in the place
-        self.sheep_in = 0;
-        for ent in self.entities.iter() {
-            match ent.entity_type {
-                EntityType::Sheep => {
-                    if self.level.cbox_overlaps_with_value(2, ent.get_collision_box()) {
-                        self.sheep_in += 1;
-                    }
-                },
-                _ => {}
+async fn run_windowed(options: Options) {
+    let Options { level_identifier, seed, players, record, mut replay, .. } = options;
+    let mut game = match Game::new(&level_identifier, seed, players) {
+        Ok(game) => game,
+        Err(error) => return error_screen(error).await,
+    };
+    let mut input_map = match Bindings::load(Path::new(INPUT_CONFIG), players) {
+        Ok(bindings) => InputMap::new(bindings),
+        Err(error) => return error_screen(error.into()).await,
+    };
+    // Tab opens the options menu, which pauses the game
+    let mut options: Option<OptionsMenu> = None;
+    let mut recording = Recording::new(seed, &level_identifier, players);
+    if record.is_some() {
+        // Closing the window must not lose the recording
+        prevent_quit();
+    }
 
+    let mut inputs = vec![PlayerInput::default(); players];
+    let mut accumulator = 0.0;
+    loop {
+        if let Some(menu) = options.as_mut() {
+            if !menu.update(&mut input_map) {
+                options = None;
+                if let Err(error) = Bindings::save(&input_map.bindings, Path::new(INPUT_CONFIG)) {
+                    eprintln!("error: can't save {}: {}", INPUT_CONFIG, error);
+                }
             }
+            game.render(accumulator / FIXED_DT);
+            if let Some(menu) = options.as_ref() {
+                menu.draw(&input_map);
+            }
+            next_frame().await;
+            continue;
+        }
+
+        if is_key_pressed(KeyCode::Escape) || is_quit_requested() {
+            break;
+        }
+        if replay.is_none() && is_key_pressed(KeyCode::Tab) {
+            options = Some(OptionsMenu::new());
+            continue;
+        }
+
+        // The simulation runs at a fixed rate, catching up with the frame time
+        let frame_time = get_frame_time().min(MAX_FRAME_TIME);
+        let result = match replay.as_mut() {
+            Some(replay) => {
+                if !replay.paused {
+                    accumulator += frame_time * replay.speed;
+                }
+                replay_controls(replay, &mut game.simulation, &mut accumulator)
+            }
+            None => {
+                input_map.poll(&mut inputs);
+                accumulator += frame_time;
+                let mut result = Ok(());
+                while accumulator >= FIXED_DT && result.is_ok() {
+                    result = game.simulation.update(FIXED_DT, &inputs);
+                    recording.push(&inputs, &game.simulation);
+                    // A key press is only used by one tick
+                    inputs.iter_mut().for_each(|input| input.consume());
+                    accumulator -= FIXED_DT;
+                }
+                result
+            }
+        };
+        if let Err(error) = result {
+            save_recording(&recording, &record);
+            return error_screen(error).await;
+        }
+
+        game.render(accumulator / FIXED_DT);
+        if let Some(replay) = replay.as_ref() {
+            draw_replay_status(replay, &game.simulation);
         }
+        //draw_text(&format!("{}", get_fps()), 30.0, 30.0, 24.0, RED);
+        next_frame().await
+    }
+
+    save_recording(&recording, &record);
+}
 
+fn save_recording(recording: &Recording, path: &Option<PathBuf>) {
+    if let Some(path) = path {
+        match recording.save(path) {
+            Ok(()) => println!("{} ticks recorded in {}", recording.len(), path.display()),
+            Err(error) => eprintln!("error: {}", error),
+        }
     }
+}
 
-    fn render(&mut self) {
+/// Seconds jumped by a seek in a replay
+const SEEK_STEP: f32 = 5.0;
 
-        // Draw ground
-        self.level.render(self.ground_texture, self.scale);
-        
-        // Sort all element before displaying (depth sorting)
-        self.entities.sort_by_key(|k| k.depth_sort());
+/// Keyboard controls of a replay, and the recorded ticks to run this frame
+///
+/// Space pauses, Up and Down change the speed, Left and Right seek.
+fn replay_controls(replay: &mut Replay, simulation: &mut Simulation, accumulator: &mut f32) -> Result<(), Box<dyn Error>> {
+    if is_key_pressed(KeyCode::Space) {
+        replay.paused = !replay.paused;
+    }
+    if is_key_pressed(KeyCode::Up) {
+        replay.speed = (replay.speed * 2.0).min(8.0);
+    }
+    if is_key_pressed(KeyCode::Down) {
+        replay.speed = (replay.speed * 0.5).max(0.25);
+    }
 
-        // ... and draw all the entities
-        for ent in self.entities.iter_mut() {
-            ent.render(self.texture, self.scale);
+    let seek_ticks = (SEEK_STEP * TICK_RATE) as u64;
+    if is_key_pressed(KeyCode::Right) {
+        replay.seek(simulation, simulation.ticks + seek_ticks, FIXED_DT)?;
+    }
+    if is_key_pressed(KeyCode::Left) {
+        replay.seek(simulation, simulation.ticks.saturating_sub(seek_ticks), FIXED_DT)?;
+    }
+
+    while *accumulator >= FIXED_DT {
+        *accumulator -= FIXED_DT;
+        if !replay.step(simulation, FIXED_DT)? {
+            *accumulator = 0.0;
         }
-        draw_text(&format!("{}", self.sheep_in), 30.0, 30.0, 24.0, RED);
     }
+    Ok(())
 }
 
-#[macroquad::main(window_conf())]
-async fn main() {
-    let mut game = Game::new();
+fn draw_replay_status(replay: &Replay, simulation: &Simulation) {
+    let mut status = format!(
+        "replay {:.1} / {:.1} s  x{}",
+        simulation.ticks as f32 * FIXED_DT,
+        replay.recording.len() as f32 * FIXED_DT,
+        replay.speed
+    );
+    if replay.paused {
+        status.push_str("  paused");
+    } else if replay.is_over(simulation) {
+        status.push_str("  over");
+    }
+    draw_text(&status, 30.0, screen_height() - 40.0, 24.0, WHITE);
+    if let Some(desync) = replay.desync.as_ref() {
+        draw_text(&desync.to_string(), 30.0, screen_height() - 70.0, 24.0, RED);
+    }
+}
+
+/// Show what went wrong instead of crashing, until the player quits
+async fn error_screen(error: Box<dyn Error>) {
+    eprintln!("error: {}", error);
+    let message = error.to_string();
     loop {
-        game.update();
+        clear_background(BLACK);
+        draw_text("Something went wrong :(", 30.0, 60.0, 32.0, RED);
+        for (i, line) in message.lines().enumerate() {
+            draw_text(line, 30.0, 110.0 + 30.0 * i as f32, 24.0, WHITE);
+        }
+        draw_text("Press Escape to quit", 30.0, screen_height() - 40.0, 24.0, GRAY);
 
-        game.render();
-        //draw_text(&format!("{}", get_fps()), 30.0, 30.0, 24.0, RED);
+        if is_key_pressed(KeyCode::Escape) {
+            break;
+        }
         next_frame().await
     }
 }
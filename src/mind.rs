@@ -0,0 +1,41 @@
+use crate::entities::Entity;
+use crate::puppet_master::Behaviour;
+
+/// Where the behaviour of an entity goes after a tick
+///
+/// Entities keep the behaviours they were interrupted in, and resume them
+/// once the interruption is over.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Transition {
+    /// Keep the current behaviour as it is
+    Stay,
+    /// Replace the current behaviour, or update its state
+    Switch(Behaviour),
+    /// Interrupt the current behaviour with another one, to resume it once
+    /// the other one is over. Behaviours not worth resuming are replaced.
+    Push(Behaviour),
+    /// The current behaviour is over, resume the last one interrupted
+    Pop,
+}
+
+/// Look of an entity around it, giving the behaviour to interrupt the
+/// current one with, if any
+pub type Reflex = fn(&Entity, &[Entity]) -> Option<Behaviour>;
+
+/// How the entities of a type behave, whatever they are doing
+pub struct Mind {
+    /// Behaviour they go back to once nothing is left to resume
+    pub root: Behaviour,
+    /// Checked in order after every tick in which they are alert
+    pub reflexes: &'static [Reflex],
+}
+
+impl Mind {
+    /// Behaviour to interrupt `ent` with, from the first reflex firing
+    pub fn react(&self, ent: &Entity, entities: &[Entity]) -> Option<Behaviour> {
+        if !ent.behaviour.is_alert() {
+            return None;
+        }
+        self.reflexes.iter().find_map(|reflex| reflex(ent, entities))
+    }
+}
@@ -0,0 +1,97 @@
+use macroquad::prelude::*;
+
+use crate::input::{Action, Binding, InputMap};
+
+/// Menu to change the keys and buttons bound to each action
+///
+/// Left and Right choose a player, Up and Down an action, Enter waits for a
+/// new key or button to bind to it, Backspace removes all its bindings and
+/// Escape closes the menu.
+pub struct OptionsMenu {
+    player: usize,
+    selected: usize,
+    /// Waiting for the key or button to bind to the selected action
+    capturing: bool,
+}
+
+impl OptionsMenu {
+    pub fn new() -> Self {
+        OptionsMenu {
+            player: 0,
+            selected: 0,
+            capturing: false,
+        }
+    }
+
+    /// Handle the menu's controls, return false once it is closed
+    pub fn update(&mut self, input_map: &mut InputMap) -> bool {
+        input_map.gamepads.update();
+        let action = Action::ALL[self.selected];
+
+        if self.capturing {
+            let binding = match get_last_key_pressed() {
+                Some(KeyCode::Escape) => {
+                    self.capturing = false;
+                    None
+                }
+                Some(key) => Binding::key(key),
+                None => input_map.gamepads.just_pressed(self.player).map(Binding::Pad),
+            };
+            if let Some(binding) = binding {
+                input_map.bindings[self.player].add(action, binding);
+                self.capturing = false;
+            }
+            return true;
+        }
+
+        if is_key_pressed(KeyCode::Escape) {
+            return false;
+        }
+        let players = input_map.bindings.len();
+        if is_key_pressed(KeyCode::Left) {
+            self.player = (self.player + players - 1) % players;
+        }
+        if is_key_pressed(KeyCode::Right) {
+            self.player = (self.player + 1) % players;
+        }
+        if is_key_pressed(KeyCode::Up) {
+            self.selected = (self.selected + Action::ALL.len() - 1) % Action::ALL.len();
+        }
+        if is_key_pressed(KeyCode::Down) {
+            self.selected = (self.selected + 1) % Action::ALL.len();
+        }
+        if is_key_pressed(KeyCode::Enter) {
+            self.capturing = true;
+        }
+        if is_key_pressed(KeyCode::Backspace) {
+            input_map.bindings[self.player].clear(action);
+        }
+        true
+    }
+
+    pub fn draw(&self, input_map: &InputMap) {
+        draw_rectangle(0.0, 0.0, screen_width(), screen_height(), Color::new(0.0, 0.0, 0.0, 0.8));
+        draw_text(
+            &format!("Controls of player {} / {}", self.player + 1, input_map.bindings.len()),
+            30.0,
+            60.0,
+            32.0,
+            WHITE,
+        );
+
+        for (i, action) in Action::ALL.iter().enumerate() {
+            let bindings: Vec<String> = input_map.bindings[self.player].get(*action).iter().map(|b| b.to_string()).collect();
+            let color = if i == self.selected { YELLOW } else { WHITE };
+            let y = 110.0 + 30.0 * i as f32;
+            draw_text(&format!("{:?}", action), 30.0, y, 24.0, color);
+            draw_text(&bindings.join(", "), 200.0, y, 24.0, color);
+        }
+
+        let help = if self.capturing {
+            "Press a key or a button to add, Escape to cancel"
+        } else {
+            "Left/Right: player  Enter: add a binding  Backspace: clear  Escape: back to the game"
+        };
+        draw_text(help, 30.0, screen_height() - 40.0, 24.0, GRAY);
+    }
+}
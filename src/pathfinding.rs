@@ -0,0 +1,331 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+use macroquad::math::{Rect, Vec2};
+
+use crate::level::Level;
+
+/// Cost of a step to a side neighbour, and to a diagonal one
+const STEP: u32 = 10;
+const DIAGONAL_STEP: u32 = 14;
+/// Paths kept before the cache is emptied
+const MAX_CACHED_PATHS: usize = 1024;
+/// How many cells ahead on its path an entity looks for a shortcut
+const LOOK_AHEAD: usize = 4;
+/// Boxes are shrunk by this, in pixels, when looking for a clear way, so
+/// that sliding along a fence doesn't count as running into it
+const CLEAR_WAY_MARGIN: f32 = 0.5;
+
+/// True for a cell entities can walk on: anything but a fence
+fn walkable(level: &Level, x: usize, y: usize) -> bool {
+    level.get_int_at(x, y).is_some_and(|value| value != 1)
+}
+
+/// Center of the cell at `index` in the collision grid
+fn cell_center(level: &Level, index: usize) -> Vec2 {
+    level.cell_box(index % level.cell_w, index / level.cell_w).center()
+}
+
+/// Cells one step away from the cell `index` that can be walked to, with the
+/// cost of the step
+///
+/// Diagonal steps don't cut the corners of fences.
+fn neighbours(level: &Level, index: usize) -> impl Iterator<Item = (usize, u32)> + '_ {
+    let x = (index % level.cell_w) as i64;
+    let y = (index / level.cell_w) as i64;
+    let open = move |dx: i64, dy: i64| x + dx >= 0 && y + dy >= 0 && walkable(level, (x + dx) as usize, (y + dy) as usize);
+    [(-1, 0), (1, 0), (0, -1), (0, 1), (-1, -1), (1, -1), (-1, 1), (1, 1)]
+        .into_iter()
+        .filter(move |&(dx, dy)| open(dx, dy) && (dx == 0 || dy == 0 || (open(dx, 0) && open(0, dy))))
+        .map(move |(dx, dy)| {
+            let cost = if dx == 0 || dy == 0 { STEP } else { DIAGONAL_STEP };
+            ((x + dx) as usize + (y + dy) as usize * level.cell_w, cost)
+        })
+}
+
+/// Cells from `start` to `goal`, both included, by the shortest way (A*)
+///
+/// None when `goal` can't be reached.
+fn find_path(level: &Level, start: usize, goal: usize) -> Option<Vec<usize>> {
+    let (goal_x, goal_y) = (goal % level.cell_w, goal / level.cell_w);
+    // Cost of the shortest way without fences
+    let estimate = |index: usize| {
+        let dx = (index % level.cell_w).abs_diff(goal_x) as u32;
+        let dy = (index / level.cell_w).abs_diff(goal_y) as u32;
+        STEP * dx.max(dy) + (DIAGONAL_STEP - STEP) * dx.min(dy)
+    };
+
+    let cells = level.cell_w * level.cell_h;
+    let mut cost = vec![u32::MAX; cells];
+    let mut came_from = vec![usize::MAX; cells];
+    // Ties are broken on the index, so the same grid always gives the same path
+    let mut open = BinaryHeap::new();
+    cost[start] = 0;
+    open.push(Reverse((estimate(start), start)));
+
+    while let Some(Reverse((_, index))) = open.pop() {
+        if index == goal {
+            let mut path = vec![goal];
+            let mut index = goal;
+            while index != start {
+                index = came_from[index];
+                path.push(index);
+            }
+            path.reverse();
+            return Some(path);
+        }
+        for (next, step) in neighbours(level, index) {
+            let next_cost = cost[index] + step;
+            if next_cost < cost[next] {
+                cost[next] = next_cost;
+                came_from[next] = index;
+                open.push(Reverse((next_cost + estimate(next), next)));
+            }
+        }
+    }
+    None
+}
+
+/// True if the box `rect` can go straight until its center is on `target`
+/// without touching a fence
+fn clear_way(level: &Level, rect: Rect, target: Vec2) -> bool {
+    let margin = CLEAR_WAY_MARGIN;
+    let rect = Rect::new(rect.x + margin, rect.y + margin, rect.w - 2.0 * margin, rect.h - 2.0 * margin);
+    let offset = target - rect.center();
+    // Steps smaller than the box, so that no corner is jumped over
+    let step = rect.w.min(rect.h).min(0.5 * level.grid_size).max(1.0);
+    let steps = (offset.length() / step).ceil() as usize;
+    (1..=steps).all(|i| !level.cbox_overlaps_with_value(1, rect.offset(offset * i as f32 / steps as f32)))
+}
+
+/// Cost of the way to the closest goal cell, from every cell of a level
+///
+/// One field leads any number of entities to the same goals, wherever they
+/// start from (Dijkstra from the goals).
+pub struct FlowField {
+    cost: Vec<u32>,
+}
+
+impl FlowField {
+    /// Field towards the walkable cells (x, y) for which `goal` is true
+    pub fn new(level: &Level, goal: impl Fn(usize, usize) -> bool) -> Self {
+        let mut cost = vec![u32::MAX; level.cell_w * level.cell_h];
+        let mut open = BinaryHeap::new();
+        for (index, cell_cost) in cost.iter_mut().enumerate() {
+            let (x, y) = (index % level.cell_w, index / level.cell_w);
+            if walkable(level, x, y) && goal(x, y) {
+                *cell_cost = 0;
+                open.push(Reverse((0, index)));
+            }
+        }
+
+        while let Some(Reverse((index_cost, index))) = open.pop() {
+            if index_cost > cost[index] {
+                continue;
+            }
+            // Steps are the same both ways
+            for (next, step) in neighbours(level, index) {
+                if index_cost + step < cost[next] {
+                    cost[next] = index_cost + step;
+                    open.push(Reverse((cost[next], next)));
+                }
+            }
+        }
+        FlowField { cost }
+    }
+
+    /// Next cell from the cell `index` towards the closest goal
+    ///
+    /// None on a goal, or when no goal can be reached.
+    pub fn next(&self, level: &Level, index: usize) -> Option<usize> {
+        neighbours(level, index)
+            .map(|(next, _)| next)
+            .min_by_key(|&next| (self.cost[next], next))
+            .filter(|&next| self.cost[next] < self.cost[index])
+    }
+}
+
+/// Ways around the fences, kept from a tick to the next until the collision
+/// grid changes
+#[derive(Default)]
+pub struct Navigation {
+    /// Collision grid everything was found on
+    grid: Vec<u8>,
+    cell_w: usize,
+    /// Path between two cells, by (start, goal)
+    paths: HashMap<(usize, usize), Option<Vec<usize>>>,
+    edge: Option<FlowField>,
+}
+
+impl Navigation {
+    /// Forget everything found on another collision grid, a new level or
+    /// fences that moved
+    pub fn update(&mut self, level: &Level) {
+        if self.cell_w != level.cell_w || self.grid != level.collision_grid {
+            self.grid = level.collision_grid.clone();
+            self.cell_w = level.cell_w;
+            self.paths.clear();
+            self.edge = None;
+        }
+    }
+
+    fn path(&mut self, level: &Level, start: usize, goal: usize) -> Option<&[usize]> {
+        if self.paths.len() >= MAX_CACHED_PATHS && !self.paths.contains_key(&(start, goal)) {
+            self.paths.clear();
+        }
+        self.paths
+            .entry((start, goal))
+            .or_insert_with(|| find_path(level, start, goal))
+            .as_deref()
+    }
+
+    /// Direction for the box `rect` to go until its center is on `target`
+    ///
+    /// Straight when nothing is in the way, else along the shortest path,
+    /// cutting the corners it can. None when `target` can't be reached.
+    pub fn towards(&mut self, level: &Level, rect: Rect, target: Vec2) -> Option<Vec2> {
+        let from = rect.center();
+        if clear_way(level, rect, target) {
+            return Some((target - from).normalize_or_zero());
+        }
+        let path = self.path(level, level.cell_index(from), level.cell_index(target))?;
+        let waypoint = path
+            .iter()
+            .skip(1)
+            .take(LOOK_AHEAD)
+            .rev()
+            .map(|&index| cell_center(level, index))
+            .find(|&waypoint| clear_way(level, rect, waypoint))
+            .or_else(|| path.get(1).map(|&index| cell_center(level, index)))
+            .unwrap_or(target);
+        Some((waypoint - from).normalize_or_zero())
+    }
+
+    /// Direction for the box `rect` to get to the closest cell on the edge of
+    /// the level, around the fences
+    ///
+    /// None once on such a cell, or when none can be reached.
+    pub fn towards_edge(&mut self, level: &Level, rect: Rect) -> Option<Vec2> {
+        let field = self.edge.get_or_insert_with(|| {
+            FlowField::new(level, |x, y| x == 0 || y == 0 || x + 1 == level.cell_w || y + 1 == level.cell_h)
+        });
+        let from = rect.center();
+        let next = field.next(level, level.cell_index(from))?;
+        Some((cell_center(level, next) - from).normalize_or_zero())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Level drawn row by row, `#` for a fence
+    fn level(rows: &[&str]) -> Level {
+        let grid = rows.iter().flat_map(|row| row.bytes().map(|c| (c == b'#') as u8)).collect();
+        Level::from_grid(rows[0].len(), rows.len(), 16.0, grid)
+    }
+
+    fn cell(level: &Level, x: usize, y: usize) -> usize {
+        x + y * level.cell_w
+    }
+
+    /// True if each cell of `path` is a step away from the one before,
+    /// never through a fence
+    fn is_walkable(level: &Level, path: &[usize]) -> bool {
+        path.iter().all(|&index| walkable(level, index % level.cell_w, index / level.cell_w))
+            && path.windows(2).all(|step| neighbours(level, step[0]).any(|(next, _)| next == step[1]))
+    }
+
+    #[test]
+    fn path_goes_around_a_wall() {
+        let level = level(&[
+            "..#..",
+            "..#..",
+            "..#..",
+            ".....",
+        ]);
+        let path = find_path(&level, cell(&level, 0, 0), cell(&level, 4, 0)).unwrap();
+        assert_eq!(path.first(), Some(&cell(&level, 0, 0)));
+        assert_eq!(path.last(), Some(&cell(&level, 4, 0)));
+        assert!(path.contains(&cell(&level, 2, 3)));
+        assert!(is_walkable(&level, &path));
+        // Straight along the wall where its end can't be cut
+        assert_eq!(path.len(), 9);
+    }
+
+    #[test]
+    fn diagonal_steps_dont_cut_corners() {
+        let level = level(&[
+            ".#.",
+            "...",
+        ]);
+        let (start, goal) = (cell(&level, 0, 0), cell(&level, 1, 1));
+        assert!(neighbours(&level, start).all(|(next, _)| next != goal));
+        assert_eq!(find_path(&level, start, goal), Some(vec![start, cell(&level, 0, 1), goal]));
+
+        let field = FlowField::new(&level, |x, y| (x, y) == (2, 0));
+        assert_eq!(field.next(&level, start), Some(cell(&level, 0, 1)));
+        assert_eq!(field.next(&level, cell(&level, 2, 0)), None);
+    }
+
+    #[test]
+    fn enclosed_goal_cant_be_reached() {
+        let level = level(&[
+            ".....",
+            ".###.",
+            ".#.#.",
+            ".###.",
+        ]);
+        let goal = cell(&level, 2, 2);
+        assert_eq!(find_path(&level, cell(&level, 0, 0), goal), None);
+
+        let field = FlowField::new(&level, |x, y| (x, y) == (2, 2));
+        assert_eq!(field.next(&level, cell(&level, 0, 0)), None);
+    }
+
+    #[test]
+    fn flow_field_leads_to_the_closest_goal() {
+        let level = level(&[
+            "......",
+            ".####.",
+            "......",
+        ]);
+        let field = FlowField::new(&level, |x, _| x == 5);
+        let mut path = vec![cell(&level, 0, 1)];
+        while let Some(next) = field.next(&level, *path.last().unwrap()) {
+            path.push(next);
+        }
+        assert_eq!(path.last().map(|index| index % level.cell_w), Some(5));
+        assert!(is_walkable(&level, &path));
+        // Not diagonally past the end of the wall
+        assert_eq!(path.len(), 7);
+    }
+
+    #[test]
+    fn paths_are_forgotten_when_the_grid_changes() {
+        let mut level = level(&[
+            "...",
+            "...",
+        ]);
+        let (start, goal) = (cell(&level, 0, 0), cell(&level, 2, 0));
+        let mut navigation = Navigation::default();
+        navigation.update(&level);
+        assert_eq!(navigation.path(&level, start, goal).map(|p| p.len()), Some(3));
+
+        // Same grid, the path is kept
+        navigation.update(&level);
+        assert_eq!(navigation.paths.len(), 1);
+
+        // A fence closes the way
+        for y in 0..2 {
+            let index = cell(&level, 1, y);
+            level.collision_grid[index] = 1;
+        }
+        navigation.update(&level);
+        assert!(navigation.paths.is_empty());
+        assert_eq!(navigation.path(&level, start, goal), None);
+
+        let rect = Rect::new(2.0, 10.0, 10.0, 6.0);
+        assert_eq!(navigation.towards(&level, rect, cell_center(&level, goal)), None);
+    }
+}
@@ -1,196 +1,448 @@
-use macroquad::input::*;
-use macroquad::math::Vec2;
-use macroquad::rand::gen_range;
+use macroquad::math::{Rect, Vec2};
 
-use crate::entities::{Entity, EntityType};
+use crate::broad_phase::BroadPhase;
+use crate::entities::{AnimationState, Entity, EntityType};
+use crate::flock::{self, FlockWeights};
+use crate::input::PlayerInput;
 use crate::level::Level;
+use crate::mind::{Mind, Reflex, Transition};
+use crate::pathfinding::Navigation;
+use crate::rng::Rng;
+use crate::sweep::{Contact, Sweep};
+
+/// Speed on the ground of a thrown entity, in pixels per second
+const THROW_SPEED: f32 = 120.0;
+/// Upward speed of a thrown entity when it leaves the hands, in pixels per second
+const THROW_LIFT: f32 = 90.0;
+/// In pixels per second squared
+const GRAVITY: f32 = 400.0;
+/// Part of its speed a thrown entity keeps when bouncing off something
+const THROW_BOUNCE: f32 = 0.5;
+/// Speeds of the hop of an entity falling on a fence or another entity, in
+/// pixels per second, on the ground and upward, enough to get off it
+const HOP_SPEED: f32 = 90.0;
+const HOP_LIFT: f32 = 60.0;
+/// Above this height, in pixels, entities fly over fences and other entities
+const FENCE_HEIGHT: f32 = 8.0;
+/// How long a sheep stays dazed after landing, in seconds
+const STUN_TIME: f32 = 1.0;
+/// Speed given to a wolf hit by a thrown entity, in pixels per second
+const KNOCK_BACK_SPEED: f32 = 180.0;
+/// How fast a knocked back entity slows down, per second
+const KNOCK_BACK_DAMPING: f32 = 6.0;
+/// How long a wolf runs away once knocked back or scared, in seconds
+const WOLF_FLEE_TIME: f32 = 1.5;
+/// How far a wolf sees the sheep it hunts, in pixels
+const WOLF_SIGHT: f32 = 120.0;
+/// Sheep closer than this to a hero, in pixels, are protected from wolves
+const PROTECTION_RADIUS: f32 = 64.0;
+/// A hero closer than this to a wolf, in pixels, scares it away
+const WOLF_FEAR_RADIUS: f32 = 48.0;
+/// Speed of a wolf sneaking towards its prey, in pixels per second
+const STALK_SPEED: f32 = 30.0;
+/// Distance from which a stalking wolf chases its prey, in pixels, before
+/// the sheep sees it
+const POUNCE_DISTANCE: f32 = 72.0;
+/// How long a wolf chases before giving up, in seconds
+const CHASE_TIME: f32 = 2.0;
+/// Distance between the centers of a wolf and a sheep it can catch, in pixels
+const CATCH_DISTANCE: f32 = 14.0;
+/// Speed of a wolf dragging a sheep away, in pixels per second
+const DRAG_SPEED: f32 = 40.0;
+/// Speed of an entity running away, in pixels per second
+const RUN_AWAY_SPEED: f32 = 60.0;
+/// How long an entity runs away, in seconds
+const RUN_AWAY_TIME: f32 = 0.33;
+/// Side of the cells of the entity collision broad phase, in pixels
+const BROAD_PHASE_CELL: f32 = 32.0;
 
 /// Behaviours enum
 ///
-/// Playable: for entity controlled by a player
+/// Playable: for entity controlled by the player `player`, counted from 0
 /// FreeWalk: for a basic random walk
+/// Flock: for a sheep moving with the others, wandering towards `wander`
 /// Transported: for an entity carried by another
-#[derive(Clone, Copy, PartialEq)]
+/// Thrown: for an entity in the air, `velocity` on the ground and
+/// `vertical_speed` upward, in pixels per second
+/// Stunned: for a sheep dazed after landing, for `time_left` seconds
+/// KnockedBack: for a wolf hit by a thrown entity, sliding away at `velocity`
+/// before running away
+/// Stalk: for a wolf sneaking towards the sheep `prey`
+/// Chase: for a wolf running after the sheep `prey`, for `time_left` seconds
+/// at most
+/// Drag: for a wolf taking the sheep it carries off the map, going `dir`
+/// Static: for an entity that never moves, like a tree
+///
+/// Behaviours say where to go next with a `Transition`: carried, thrown or
+/// scared entities go back to what they were doing once it's over.
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Behaviour {
-    Playable,
+    Playable { player: usize },
     FreeWalk,
+    Flock { wander: Vec2 },
     Transported,
-    Thrown { dir: Vec2, yo: f32, h: f32, thrower: u32},
+    Thrown { velocity: Vec2, vertical_speed: f32, thrower: u32 },
     DumbDog,
-    RunAway {dir: Vec2, running_time: i32},
+    /// `running_time` is in seconds
+    RunAway {dir: Vec2, running_time: f32},
+    Stunned { time_left: f32 },
+    KnockedBack { velocity: Vec2 },
+    Stalk { prey: u32 },
+    Chase { prey: u32, time_left: f32 },
+    Drag { dir: Vec2 },
+    Static,
 }
 
+impl Behaviour {
+    /// Behaviour given by its name in the level editor
+    pub fn from_name(name: &str) -> Option<Behaviour> {
+        match name {
+            "Playable" => Some(Behaviour::Playable { player: 0 }),
+            "FreeWalk" => Some(Behaviour::FreeWalk),
+            "Flock" => Some(Behaviour::Flock { wander: Vec2::ZERO }),
+            "DumbDog" => Some(Behaviour::DumbDog),
+            "Static" => Some(Behaviour::Static),
+            _ => None,
+        }
+    }
+
+    /// True for the behaviours an entity goes back to once interrupted, the
+    /// others are given up
+    pub fn is_resumable(&self) -> bool {
+        matches!(
+            self,
+            Behaviour::Playable { .. } | Behaviour::FreeWalk | Behaviour::Flock { .. } | Behaviour::DumbDog | Behaviour::Static
+        )
+    }
+
+    /// True for the behaviours the reflexes of an entity can interrupt
+    pub fn is_alert(&self) -> bool {
+        matches!(
+            self,
+            Behaviour::FreeWalk
+                | Behaviour::Flock { .. }
+                | Behaviour::DumbDog
+                | Behaviour::Stalk { .. }
+                | Behaviour::Chase { .. }
+                | Behaviour::Drag { .. }
+        )
+    }
+}
+
+const HERO_MIND: Mind = Mind { root: Behaviour::FreeWalk, reflexes: &[] };
+const SHEEP_MIND: Mind = Mind {
+    root: Behaviour::Flock { wander: Vec2::ZERO },
+    reflexes: &[flee_wolves as Reflex],
+};
+const WOLF_MIND: Mind = Mind {
+    root: Behaviour::DumbDog,
+    reflexes: &[fear_heroes as Reflex],
+};
+const TREE_MIND: Mind = Mind { root: Behaviour::Static, reflexes: &[] };
+
+/// How the entities of each type behave
+pub fn mind(entity_type: EntityType) -> &'static Mind {
+    match entity_type {
+        EntityType::Hero => &HERO_MIND,
+        EntityType::Sheep => &SHEEP_MIND,
+        EntityType::Wolf => &WOLF_MIND,
+        EntityType::Tree => &TREE_MIND,
+    }
+}
+
+
+/// True, on average, `rate` times per second
+fn happens(rng: &mut Rng, rate: f32, dt: f32) -> bool {
+    rng.gen_range(0.0, 1.0) < rate * dt
+}
 
 /// the main puppet_master's function
-pub fn play(entities: &mut Vec<Entity>, level: &Level) {
+///
+/// Run a simulation tick of `dt` seconds, with the input of each player
+///
+/// `navigation` keeps the ways found around the fences from a tick to the next,
+/// `weights` is how the sheep of the level flock.
+pub fn play(
+    entities: &mut [Entity],
+    level: &Level,
+    navigation: &mut Navigation,
+    weights: &FlockWeights,
+    inputs: &[PlayerInput],
+    rng: &mut Rng,
+    dt: f32,
+) {
+    navigation.update(level);
+
+    // Keep where everything was, to draw between two ticks
+    for ent in entities.iter_mut() {
+        ent.previous_position = ent.position;
+        ent.previous_height = ent.height;
+    }
+
     // Apply each entity's behaviours
     for i in 0..entities.len() {
         let mut ent = entities[i].clone();
-        match ent.behaviour {
-            Behaviour::Playable => playable(&mut ent, entities),
-            Behaviour::FreeWalk => free_walk(&mut ent, entities),
+        let transition = match ent.behaviour {
+            Behaviour::Playable { player } => {
+                // Nobody plays this hero without input for its player
+                let input = inputs.get(player).copied().unwrap_or_default();
+                playable(&mut ent, entities, &input, dt)
+            }
+            Behaviour::FreeWalk => free_walk(&mut ent, entities, weights, rng, dt),
+            Behaviour::Flock { wander } => flock(&mut ent, entities, wander, weights, rng, dt),
             Behaviour::Transported => transported(&mut ent, entities),
-            Behaviour::Thrown { dir, yo, h, thrower } => thrown(&mut ent, dir, yo, h, thrower),
-            Behaviour::DumbDog => dumb_dog(&mut ent),
-            Behaviour::RunAway { dir, running_time } => run_away(&mut ent, dir, running_time),
-            _ => {}
+            Behaviour::Thrown { velocity, vertical_speed, thrower } => {
+                thrown(&mut ent, entities, level, velocity, vertical_speed, thrower, dt)
+            }
+            Behaviour::DumbDog => dumb_dog(&mut ent, entities, level, rng, dt),
+            Behaviour::RunAway { dir, running_time } => run_away(&mut ent, dir, running_time, dt),
+            Behaviour::Stunned { time_left } => stunned(&mut ent, time_left, dt),
+            Behaviour::KnockedBack { velocity } => knocked_back(&mut ent, velocity, dt),
+            Behaviour::Stalk { prey } => stalk(&mut ent, entities, level, navigation, prey, dt),
+            Behaviour::Chase { prey, time_left } => {
+                chase(&mut ent, entities, level, navigation, prey, time_left, dt)
+            }
+            Behaviour::Drag { .. } => drag(&mut ent, entities, level, navigation, dt),
+            Behaviour::Static => Transition::Stay,
+        };
+        change(&mut ent, entities, transition);
+
+        if let Some(behaviour) = mind(ent.entity_type).react(&ent, entities) {
+            change(&mut ent, entities, Transition::Push(behaviour));
         }
-        
+
         // Replace by the new updated entity
         entities[i] = ent;
     }
 
     // Check collision between entities
-    entity_entity_collision(entities, level);
+    entity_entity_collision(entities, level, dt);
 
     // Make all entities move
-    motion(entities);
-}
+    motion(entities, dt);
 
-/// Check collision between entities
-pub fn entity_entity_collision(entities: &mut Vec<Entity>, level: &Level) {
-    // Collision detection
-    // detection on x and y to allow collide and slide
-    for i in 0..entities.len() {
-        let mut ent = entities[i].clone();
+    // Animation events are seen by the behaviours of the next call
+    animate(entities, dt);
+}
 
-        // First, we need to stay in the playground
-        let collider = ent.get_collision_box();
-        if collider.x < 8.0 || collider.x + collider.w > level.width - 8.0 {
-            ent.direction.x = 0.0;
-            ent.velocity.x = 0.0; 
-        }
-        if collider.y < 8.0 || collider.y + collider.h > level.height {
-            ent.direction.y = 0.0;
-            ent.velocity.y = 0.0; 
+/// Stop entities at what they run into, keeping what they touched in their `contacts`
+///
+/// Each entity moves on x then on y, so it slides along what stops it on an
+/// axis.
+pub fn entity_entity_collision(entities: &mut [Entity], level: &Level, dt: f32) {
+    // Only entities whose boxes, before or after moving, share a cell can
+    // collide. Stopping an entity only shrinks where it goes, so its box is
+    // still in the right cells once the entities before it are stopped.
+    let mut broad_phase = BroadPhase::new(level.width, level.height, BROAD_PHASE_CELL);
+    for (i, ent) in entities.iter().enumerate() {
+        if can_collide(ent) {
+            broad_phase.insert(i, &swept_box(ent, dt));
         }
+    }
 
+    let playground = playground(level);
 
-        for j in 0..entities.len() {
-            // Avoid collison if transported or with a transported thing
-            let avoid_collision = match (ent.behaviour, entities[j].behaviour) {
-                (_, Behaviour::Transported) | (Behaviour::Transported, _) => true,
-                _ => false,
-            };
-
-            let collision = ent.collidable && !avoid_collision && entities[j].collidable && ent.id != entities[j].id;
+    let mut candidates = Vec::new();
+    for i in 0..entities.len() {
+        let ent = &entities[i];
+        if can_collide(ent) {
+            broad_phase.query(&swept_box(ent, dt), &mut candidates);
+        } else {
+            candidates.clear();
+        }
 
-            // On x
-            if collision
-                && ent
-                    .get_collision_box_diff(true, false)
-                    .overlaps(&entities[j].get_collision_box())
-            {
-                ent.direction.x = 0.0;
-                ent.velocity.x = 0.0;
+        let stop = |sweep: &mut Sweep| {
+            sweep.inside(&playground);
+            if ent.height > FENCE_HEIGHT {
+                return;
             }
-
-            // on y
-            if collision
-                && ent
-                    .get_collision_box_diff(false, true)
-                    .overlaps(&entities[j].get_collision_box())
-            {
-                ent.direction.y = 0.0;
-                ent.velocity.y = 0.0;
+            for wall in level.cboxes_with_value(1, sweep.area()) {
+                sweep.against(&wall, None);
             }
-        }
-        // Collision, background collision grid... a basic one
-        // on x
-        if  level.cbox_overlaps_with_value(1, ent.get_collision_box_diff(true, false)){
+            for &j in candidates.iter() {
+                let other = &entities[j];
+                // Where the ones before have been stopped, the others haven't moved yet
+                let other_box = if j < i { other.get_collision_box_diff(true, true, dt) } else { other.get_collision_box() };
+                if other.id != ent.id {
+                    sweep.against(&other_box, Some(other.id));
+                }
+            }
+        };
+        let wanted = ent.velocity * dt;
+        let rect = ent.get_collision_box();
+        let mut on_x = Sweep::new(rect, true, wanted.x);
+        stop(&mut on_x);
+        // From where the move on x ended
+        let mut on_y = Sweep::new(rect.offset(Vec2::new(on_x.delta(), 0.0)), false, wanted.y);
+        stop(&mut on_y);
 
+        let ent = &mut entities[i];
+        ent.contacts.clear();
+        if let Some(contact) = on_x.contact {
             ent.direction.x = 0.0;
-            ent.velocity.x = 0.0; 
+            ent.velocity.x = on_x.delta() / dt;
+            ent.contacts.push(contact);
         }
-
-        // on y
-        if  level.cbox_overlaps_with_value(1, ent.get_collision_box_diff(false, true)){
+        if let Some(contact) = on_y.contact {
             ent.direction.y = 0.0;
-            ent.velocity.y = 0.0;
+            ent.velocity.y = on_y.delta() / dt;
+            ent.contacts.push(contact);
         }
+    }
+}
 
-        // Replace by the new updated entity
-        entities[i] = ent;
+/// Where the entities must stay
+fn playground(level: &Level) -> Rect {
+    Rect::new(8.0, 8.0, level.width - 16.0, level.height - 8.0)
+}
+
+/// Carried entities, the ones flying high and the ones that aren't collidable
+/// go through the others
+fn can_collide(ent: &Entity) -> bool {
+    ent.collidable && ent.behaviour != Behaviour::Transported && ent.height <= FENCE_HEIGHT
+}
+
+/// Collision box of `ent` now and after moving for `dt` seconds
+fn swept_box(ent: &Entity, dt: f32) -> Rect {
+    ent.get_collision_box().combine_with(ent.get_collision_box_diff(true, true, dt))
+}
+
+pub fn motion(entities: &mut [Entity], dt: f32) {
+    for ent in entities.iter_mut() {
+        ent.motion(dt);
     }
 }
 
-pub fn motion(entities: &mut Vec<Entity>) {
+pub fn animate(entities: &mut [Entity], dt: f32) {
     for ent in entities.iter_mut() {
-        ent.motion();
+        ent.update_animation(dt);
     }
 }
 
 
 /// For Playable behaviour
-fn playable(ent: &mut Entity, entities: &mut Vec<Entity>) {
-    ent.direction.x = match (is_key_down(KeyCode::Left), is_key_down(KeyCode::Right)) {
-        (true, true) | (false, false) => 0.0,
-        (true, false) => -1.0,
-        (false, true) => 1.0,
-    };
-
-    ent.direction.y = match (is_key_down(KeyCode::Up), is_key_down(KeyCode::Down)) {
-        (true, true) | (false, false) => 0.0,
-        (true, false) => -1.0,
-        (false, true) => 1.0,
-    };
+fn playable(ent: &mut Entity, entities: &mut [Entity], input: &PlayerInput, dt: f32) -> Transition {
+    ent.direction = input.direction;
 
-    // Check if entity take somthing or drop something
-    if is_key_pressed(KeyCode::Space) {
+    // Check if entity take somthing, drop or throw something
+    if input.grab || input.throw {
         match ent.thing_carried {
             Some(id) => {
+                // Throwing without moving just drops
                 for other in entities.iter_mut() {
-                    if ent.direction != Vec2::ZERO && other.id == id {
-                        other.thrown(ent.direction, ent.position.y, ent.id);
+                    if input.throw && ent.direction != Vec2::ZERO && other.id == id {
+                        let dir = ent.direction.normalize();
+                        let strength = ent.throw_strength;
+                        other.thrown(dir * THROW_SPEED * strength, THROW_LIFT * strength, ent.position.y, ent.id);
                         ent.drop();
                     }
-                    else if ent.direction == Vec2::ZERO && other.id == id {
+                    else if other.id == id {
                         other.dropped(ent.position.y);
                         ent.drop();
                     }
                 }
             }
+            None if !input.grab => {}
             None => {
-                for other in entities.iter_mut() {
-                    let dist = (ent.get_collision_box().center()
-                        - other.get_collision_box().center())
-                    .length_squared();
-                    if other.id != ent.id && dist < 100.0 {
-                        ent.take(other.id);
-                        other.taken_by(ent.id, ent.position.x +  ent.collision_box.w * 0.5 - other.collision_box.x - 0.5 * other.collision_box.w);
-                    }
+                let center = ent.get_collision_box().center();
+                // A wolf is only taken once it has dropped its prey, and a
+                // sheep only from a wolf, not from another hero
+                let taken = entities.iter().position(|other| {
+                    other.id != ent.id
+                        && other.carriable
+                        && other.thing_carried.is_none()
+                        && other.transporter.is_none_or(|t| entities.iter().any(|o| o.id == t && o.entity_type == EntityType::Wolf))
+                        && (center - other.get_collision_box().center()).length_squared() < 100.0
+                });
+                if let Some(other) = taken.map(|index| &mut entities[index]) {
+                    ent.take(other.id);
+                    other.taken_by(ent.id, ent.position.x +  ent.collision_box.w * 0.5 - other.collision_box.x - 0.5 * other.collision_box.w);
                 }
             }
         }
     }
 
-    ent.apply_direction();
+    ent.apply_direction(dt);
+    Transition::Stay
 }
 
-/// For FreeWalk behaviour
-fn free_walk(ent: &mut Entity, entities: &Vec<Entity>) {
-    if gen_range(0, 100) < 2 {
-        let alea = gen_range(0, 6);
-        match alea {
-            0 => ent.direction = Vec2::new(0.0, 1.0),
-            1 => ent.direction = Vec2::new(0.0, -1.0),
-            2 => ent.direction = Vec2::new(1.0, 0.0),
-            3 => ent.direction = Vec2::new(-1.0, 0.0),
-            _ => ent.direction = Vec2::ZERO,
-        }
+/// Random walk of a sheep going `dir`: now and then a new direction, or a
+/// stop, and a bleat calling the others
+fn random_walk(ent: &mut Entity, dir: Vec2, rng: &mut Rng, dt: f32) -> Vec2 {
+    let mut dir = dir;
+    if happens(rng, 1.2, dt) {
+        dir = match rng.gen_range(0, 6) {
+            0 => Vec2::new(0.0, 1.0),
+            1 => Vec2::new(0.0, -1.0),
+            2 => Vec2::new(1.0, 0.0),
+            3 => Vec2::new(-1.0, 0.0),
+            _ => Vec2::ZERO,
+        };
     }
 
+    // Sometimes, a sheep calls the others
+    if happens(rng, 0.12, dt) {
+        ent.play_once(AnimationState::Bleat);
+    }
+    dir
+}
+
+/// For FreeWalk behaviour
+fn free_walk(ent: &mut Entity, entities: &[Entity], weights: &FlockWeights, rng: &mut Rng, dt: f32) -> Transition {
+    ent.direction = random_walk(ent, ent.direction, rng, dt);
+
+    let mut push = Vec2::ZERO;
     for other in entities.iter() {
-        if other.entity_type == EntityType::Wolf && ent.position.distance_squared(other.position) < 4000.0 {
-            let dir = (ent.position - other.position).normalize();
-            ent.behaviour = Behaviour::RunAway { dir, running_time: 20 }
+        let distance = ent.position.distance_squared(other.position);
+
+        // Walk away from a hero coming close
+        if other.entity_type == EntityType::Hero {
+            push += flock::herding_pressure(ent, other, weights);
+        }
+
+        // Go towards a calling sheep
+        if other.id != ent.id && other.has_event("bleat") && distance < 3000.0 {
+            let to_other = other.position - ent.position;
+            ent.direction = if to_other.x.abs() > to_other.y.abs() {
+                Vec2::new(to_other.x.signum(), 0.0)
+            } else {
+                Vec2::new(0.0, to_other.y.signum())
+            };
         }
     }
-    ent.apply_direction();
+    if push.length() > weights.rest {
+        ent.direction = push.clamp_length_max(1.0);
+    }
+    ent.apply_direction(dt);
+    Transition::Stay
+}
+
+/// A wolf is seen when close, and heard a bit further
+fn spots_wolf(ent: &Entity, other: &Entity) -> bool {
+    let distance = ent.position.distance_squared(other.position);
+    // A stalking wolf is silent
+    let heard = other.has_event("footstep") && !matches!(other.behaviour, Behaviour::Stalk { .. });
+    other.entity_type == EntityType::Wolf && (distance < 4000.0 || (heard && distance < 8000.0))
+}
+
+/// Reflex of a sheep spotting a wolf, running away from it
+fn flee_wolves(ent: &Entity, entities: &[Entity]) -> Option<Behaviour> {
+    entities.iter().find(|other| spots_wolf(ent, other)).map(|wolf| Behaviour::RunAway {
+        dir: (ent.position - wolf.position).normalize_or_zero(),
+        running_time: RUN_AWAY_TIME,
+    })
+}
+
+/// For Flock behaviour
+fn flock(ent: &mut Entity, entities: &[Entity], wander: Vec2, weights: &FlockWeights, rng: &mut Rng, dt: f32) -> Transition {
+    let wander = random_walk(ent, wander, rng, dt);
+    ent.direction = flock::steer(ent, entities, wander, weights);
+    ent.apply_direction(dt);
+    Transition::Switch(Behaviour::Flock { wander })
 }
 
 /// For Transportesd behaviour
-fn transported(ent: &mut Entity, entities: &mut Vec<Entity>) {
+fn transported(ent: &mut Entity, entities: &mut [Entity]) -> Transition {
     for other in entities.iter() {
         if other.id == ent.transporter.unwrap() {
             // To keep the entity in the right direction
@@ -203,36 +455,128 @@ fn transported(ent: &mut Entity, entities: &mut Vec<Entity>) {
             ent.direction = other.direction;
         }
     }
+    Transition::Stay
 }
 
-fn thrown(ent: &mut Entity, dir: Vec2, yo: f32, h: f32, thrower: u32) {
-    ent.direction = dir;
-    if dir.y == 0.0 {
-        ent.direction.y = -0.25
-    } else if dir.y * dir.y != 1.0 {
-        ent.direction.y += 0.025;
+/// Flight of a thrown entity, falling until it lands
+fn thrown(
+    ent: &mut Entity,
+    entities: &mut [Entity],
+    level: &Level,
+    velocity: Vec2,
+    vertical_speed: f32,
+    thrower: u32,
+    dt: f32,
+) -> Transition {
+    let mut vertical_speed = vertical_speed - GRAVITY * dt;
+
+    // Wolves hit on the way are knocked back
+    for contact in ent.contacts.iter() {
+        if let Some(id) = contact.entity {
+            hit(entities, id, velocity);
+        }
     }
-    if h - 0.4 <= 0.0 {
-        // Restore the default behaviour  for each type of entity
+    let mut velocity = bounce(velocity, &ent.contacts, THROW_BOUNCE);
+
+    let mut height = ent.height + vertical_speed * dt;
+    // Coming down on a fence or an entity, hop back instead of landing inside
+    if ent.height > FENCE_HEIGHT && height <= FENCE_HEIGHT {
+        if let Some((obstacle, id)) = obstacle_under(ent, entities, level) {
+            if let Some(id) = id {
+                hit(entities, id, velocity);
+            }
+            let back = (-velocity)
+                .try_normalize()
+                .unwrap_or_else(|| (ent.get_collision_box().center() - obstacle.center()).normalize_or_zero());
+            velocity = back * HOP_SPEED;
+            vertical_speed = HOP_LIFT;
+            height = FENCE_HEIGHT;
+        }
+    }
+
+    ent.velocity = velocity;
+    ent.direction = velocity.normalize_or_zero();
+    if height <= 0.0 {
+        ent.height = 0.0;
+        ent.direction = Vec2::ZERO;
+        // Sheep are dazed by the fall
         match ent.entity_type {
-            EntityType::Wolf => ent.behaviour = Behaviour::DumbDog,
-            _ => ent.behaviour = Behaviour::FreeWalk,
+            EntityType::Sheep => Transition::Switch(Behaviour::Stunned { time_left: STUN_TIME }),
+            _ => Transition::Pop,
         }
+    } else {
+        ent.height = height;
+        Transition::Switch(Behaviour::Thrown {
+            velocity,
+            vertical_speed,
+            thrower,
+        })
     }
+}
+
+/// Box of the fence or the entity `ent` is over, with the entity's id, if any
+fn obstacle_under(ent: &Entity, entities: &[Entity], level: &Level) -> Option<(Rect, Option<u32>)> {
+    let rect = ent.get_collision_box();
+    // Only touching one isn't being over it
+    let inside = |(other, _): &(Rect, Option<u32>)| rect.intersect(*other).is_some_and(|i| i.w > 0.0 && i.h > 0.0);
+    level
+        .cboxes_with_value(1, rect)
+        .map(|cbox| (cbox, None))
+        .chain(
+            entities
+                .iter()
+                .filter(|o| o.id != ent.id && can_collide(o))
+                .map(|o| (o.get_collision_box(), Some(o.id))),
+        )
+        .find(inside)
+}
+
+/// The entity `id` is hit by a thrown entity going at `velocity`
+///
+/// Only wolves on the ground care: they drop their prey, are knocked back,
+/// then run away.
+fn hit(entities: &mut [Entity], id: u32, velocity: Vec2) {
+    let Some(wolf) = entities
+        .iter_mut()
+        .find(|o| o.id == id && o.entity_type == EntityType::Wolf && o.height == 0.0)
     else {
-        ent.behaviour = Behaviour::Thrown {
-            dir: ent.direction,
-            yo,
-            h: h - 0.4,
-            thrower,
-        };
+        return;
+    };
+    let dir = velocity.normalize_or_zero();
+    wolf.apply(Transition::Push(Behaviour::KnockedBack { velocity: dir * KNOCK_BACK_SPEED }));
+    let carried = wolf.thing_carried.take();
+    let y = wolf.position.y;
+    if let Some(prey) = entities.iter_mut().find(|o| Some(o.id) == carried) {
+        prey.dropped(y);
+    }
+}
+
+/// `velocity` bounced off everything in `contacts`, keeping `restitution`
+/// of the speed going into them
+fn bounce(velocity: Vec2, contacts: &[Contact], restitution: f32) -> Vec2 {
+    let mut velocity = velocity;
+    for contact in contacts.iter() {
+        let into = velocity.dot(contact.normal);
+        if into < 0.0 {
+            velocity -= (1.0 + restitution) * into * contact.normal;
+        }
     }
-    ent.apply_direction_with_speed(2.0);
+    velocity
 }
 
-fn dumb_dog(ent: &mut Entity) {
-    if gen_range(0, 100) < 2 {
-        let alea = gen_range(0, 6);
+/// True for a sheep in the pen, as soon as it is on the ground there
+///
+/// Sheep carried or in the air above the pen don't count.
+pub fn is_penned(ent: &Entity, level: &Level) -> bool {
+    ent.entity_type == EntityType::Sheep
+        && !matches!(ent.behaviour, Behaviour::Transported | Behaviour::Thrown { .. })
+        && level.cbox_overlaps_with_value(2, ent.get_collision_box())
+}
+
+/// Wolf roaming, until it spots a sheep to hunt
+fn dumb_dog(ent: &mut Entity, entities: &[Entity], level: &Level, rng: &mut Rng, dt: f32) -> Transition {
+    if happens(rng, 1.2, dt) {
+        let alea = rng.gen_range(0, 6);
         match alea {
             0 => ent.direction = Vec2::new(1.0, 1.0),
             1 => ent.direction = Vec2::new(1.0, -1.0),
@@ -241,15 +585,242 @@ fn dumb_dog(ent: &mut Entity) {
             _ => ent.direction = Vec2::ZERO,
         }
     }
-    ent.apply_direction();
+
+    // Bounce off what it ran into
+    for contact in ent.contacts.iter() {
+        if contact.normal.x != 0.0 {
+            ent.direction.x = contact.normal.x;
+        }
+        if contact.normal.y != 0.0 {
+            ent.direction.y = contact.normal.y;
+        }
+    }
+    ent.apply_direction(dt);
+
+    // Back to roaming once the hunt is over
+    match nearest_prey(ent, entities, level) {
+        Some(prey) => Transition::Push(Behaviour::Stalk { prey }),
+        None => Transition::Stay,
+    }
+}
+
+/// Closest sheep a wolf can hunt: in sight, on the ground, out of the pen
+/// and far from the heroes
+fn nearest_prey(wolf: &Entity, entities: &[Entity], level: &Level) -> Option<u32> {
+    // Sight first, looking for the heroes around every sheep is much slower
+    entities
+        .iter()
+        .map(|o| (o, wolf.position.distance_squared(o.position)))
+        .filter(|(o, distance)| *distance < WOLF_SIGHT * WOLF_SIGHT && can_be_hunted(o, entities, level))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(o, _)| o.id)
+}
+
+fn can_be_hunted(sheep: &Entity, entities: &[Entity], level: &Level) -> bool {
+    sheep.entity_type == EntityType::Sheep
+        && sheep.height == 0.0
+        && !matches!(sheep.behaviour, Behaviour::Transported | Behaviour::Thrown { .. })
+        && !is_penned(sheep, level)
+        && !entities.iter().any(|o| {
+            o.entity_type == EntityType::Hero
+                && o.position.distance_squared(sheep.position) < PROTECTION_RADIUS * PROTECTION_RADIUS
+        })
+}
+
+/// The sheep `prey` if it can still be hunted
+fn find_prey<'a>(prey: u32, entities: &'a [Entity], level: &Level) -> Option<&'a Entity> {
+    entities.iter().find(|o| o.id == prey && can_be_hunted(o, entities, level))
+}
+
+/// Reflex of a wolf seeing a hero close, running away and giving up its hunt
+fn fear_heroes(ent: &Entity, entities: &[Entity]) -> Option<Behaviour> {
+    let hero = entities.iter().find(|o| {
+        o.entity_type == EntityType::Hero
+            && o.position.distance_squared(ent.position) < WOLF_FEAR_RADIUS * WOLF_FEAR_RADIUS
+    })?;
+    let dir = (ent.position - hero.position).normalize_or_zero();
+    Some(Behaviour::RunAway { dir, running_time: WOLF_FLEE_TIME })
+}
+
+/// Apply `transition` to `ent`, cleaning up after the behaviour it leaves
+/// for good
+fn change(ent: &mut Entity, entities: &mut [Entity], transition: Transition) {
+    let left = match transition {
+        Transition::Stay => None,
+        Transition::Push(_) if ent.behaviour.is_resumable() => None,
+        Transition::Switch(next) if std::mem::discriminant(&next) == std::mem::discriminant(&ent.behaviour) => None,
+        _ => Some(ent.behaviour),
+    };
+    ent.apply(transition);
+    if let Some(left) = left {
+        exit(left, ent, entities);
+    }
 }
 
-fn run_away(ent: &mut Entity, dir: Vec2, running_time: i32) {
+/// What a behaviour leaves behind once over or interrupted
+fn exit(behaviour: Behaviour, ent: &mut Entity, entities: &mut [Entity]) {
+    // A wolf no longer dragging its prey drops it
+    if let Behaviour::Drag { .. } = behaviour {
+        let carried = ent.thing_carried;
+        if let Some(sheep) = entities.iter_mut().find(|o| Some(o.id) == carried && o.transporter == Some(ent.id)) {
+            sheep.dropped(ent.position.y);
+        }
+        ent.drop();
+    }
+}
+
+/// Way for a wolf to its prey `sheep`, around the fences
+fn way_to_prey(ent: &Entity, sheep: &Entity, level: &Level, navigation: &mut Navigation) -> Option<Vec2> {
+    navigation.towards(level, ent.get_collision_box(), sheep.get_collision_box().center())
+}
+
+fn stalk(
+    ent: &mut Entity,
+    entities: &[Entity],
+    level: &Level,
+    navigation: &mut Navigation,
+    prey: u32,
+    dt: f32,
+) -> Transition {
+    // A sheep out of reach isn't worth it
+    let Some((sheep, dir)) =
+        find_prey(prey, entities, level).and_then(|sheep| Some((sheep, way_to_prey(ent, sheep, level, navigation)?)))
+    else {
+        return Transition::Pop;
+    };
+    let to_prey = sheep.position - ent.position;
+    ent.direction = dir;
+    ent.apply_direction_with_speed(STALK_SPEED, dt);
+
+    if to_prey.length() < POUNCE_DISTANCE {
+        Transition::Switch(Behaviour::Chase { prey, time_left: CHASE_TIME })
+    } else {
+        Transition::Stay
+    }
+}
+
+fn chase(
+    ent: &mut Entity,
+    entities: &mut [Entity],
+    level: &Level,
+    navigation: &mut Navigation,
+    prey: u32,
+    time_left: f32,
+    dt: f32,
+) -> Transition {
+    let Some((sheep, dir)) =
+        find_prey(prey, entities, level).and_then(|sheep| Some((sheep, way_to_prey(ent, sheep, level, navigation)?)))
+    else {
+        return Transition::Pop;
+    };
+    let to_prey = sheep.get_collision_box().center() - ent.get_collision_box().center();
+    ent.direction = dir;
+    ent.apply_direction(dt);
+
+    if to_prey.length() < CATCH_DISTANCE {
+        // Caught, off the map with it
+        let dir = way_out(ent, level, navigation);
+
+        let x = ent.position.x + ent.collision_box.x + 0.5 * ent.collision_box.w;
+        if let Some(sheep) = entities.iter_mut().find(|o| o.id == prey) {
+            let at = x - sheep.collision_box.x - 0.5 * sheep.collision_box.w;
+            sheep.taken_by(ent.id, at);
+        }
+        ent.take(prey);
+        Transition::Switch(Behaviour::Drag { dir })
+    } else if time_left <= 0.0 {
+        Transition::Pop
+    } else {
+        Transition::Switch(Behaviour::Chase { prey, time_left: time_left - dt })
+    }
+}
+
+/// Nearest edge of the playground from the center of `rect`
+fn nearest_exit(rect: Rect, level: &Level) -> Vec2 {
+    let area = playground(level);
+    let center = rect.center();
+    let exits = [
+        (center.x - area.left(), Vec2::new(-1.0, 0.0)),
+        (area.right() - center.x, Vec2::new(1.0, 0.0)),
+        (center.y - area.top(), Vec2::new(0.0, -1.0)),
+        (area.bottom() - center.y, Vec2::new(0.0, 1.0)),
+    ];
+    exits.iter().min_by(|a, b| a.0.total_cmp(&b.0)).map(|e| e.1).unwrap_or_default()
+}
+
+/// Way for a wolf off the map: around the fences to the closest edge cell,
+/// then straight out
+fn way_out(ent: &Entity, level: &Level, navigation: &mut Navigation) -> Vec2 {
+    let cbox = ent.get_collision_box();
+    navigation.towards_edge(level, cbox).unwrap_or_else(|| nearest_exit(cbox, level))
+}
+
+/// Dragging a sheep off the map, dropped in `exit` when scared or hit
+fn drag(ent: &mut Entity, entities: &mut [Entity], level: &Level, navigation: &mut Navigation, dt: f32) -> Transition {
+    // The sheep may have been taken back by a hero
+    let carried = ent.thing_carried;
+    let Some(sheep) = entities.iter_mut().find(|o| Some(o.id) == carried && o.transporter == Some(ent.id)) else {
+        ent.drop();
+        return Transition::Pop;
+    };
+
+    let dir = way_out(ent, level, navigation);
+    ent.direction = dir;
+    ent.apply_direction_with_speed(DRAG_SPEED, dt);
+
+    // Out of the map, the sheep is lost
+    let area = playground(level);
+    let cbox = ent.get_collision_box();
+    let reached = cbox.left() <= area.left() + 0.5
+        || cbox.right() >= area.right() - 0.5
+        || cbox.top() <= area.top() + 0.5
+        || cbox.bottom() >= area.bottom() - 0.5;
+    if reached {
+        sheep.gone = true;
+        ent.drop();
+        Transition::Pop
+    } else {
+        Transition::Switch(Behaviour::Drag { dir })
+    }
+}
+
+fn run_away(ent: &mut Entity, dir: Vec2, running_time: f32, dt: f32) -> Transition {
+    // Slide along what is in the way
+    let mut dir = dir;
+    for contact in ent.contacts.iter() {
+        let into = dir.dot(contact.normal);
+        if into < 0.0 {
+            dir = (dir - into * contact.normal).normalize_or_zero();
+        }
+    }
     ent.direction = dir;
     
-    if running_time > 0 {
-        ent.behaviour = Behaviour::RunAway { dir, running_time: running_time - 1};
-        ent.apply_direction_with_speed(1.0);
+    if running_time > 0.0 {
+        ent.apply_direction_with_speed(RUN_AWAY_SPEED, dt);
+        Transition::Switch(Behaviour::RunAway { dir, running_time: running_time - dt})
+    }
+    else {Transition::Pop}
+}
+
+fn stunned(ent: &mut Entity, time_left: f32, dt: f32) -> Transition {
+    ent.direction = Vec2::ZERO;
+    ent.apply_direction(dt);
+    if time_left > 0.0 {
+        Transition::Switch(Behaviour::Stunned { time_left: time_left - dt })
+    } else {
+        Transition::Pop
     }
-    else {ent.behaviour = Behaviour::FreeWalk}
+}
+
+fn knocked_back(ent: &mut Entity, velocity: Vec2, dt: f32) -> Transition {
+    let velocity = bounce(velocity, &ent.contacts, THROW_BOUNCE);
+    ent.velocity = velocity;
+    ent.direction = Vec2::ZERO;
+
+    let velocity = velocity * (-KNOCK_BACK_DAMPING * dt).exp();
+    Transition::Switch(if velocity.length() > RUN_AWAY_SPEED {
+        Behaviour::KnockedBack { velocity }
+    } else {
+        Behaviour::RunAway { dir: velocity.normalize_or_zero(), running_time: WOLF_FLEE_TIME }
+    })
 }
@@ -0,0 +1,348 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use macroquad::math::Vec2;
+use serde::{Deserialize, Serialize};
+
+use crate::input::PlayerInput;
+use crate::simulation::Simulation;
+
+/// Ticks between two checksums of the simulation in a recording
+pub const CHECKSUM_INTERVAL: u64 = 60;
+/// Version of the recording files written, to bump when their content changes
+const FORMAT_VERSION: u32 = 1;
+
+/// Everything that can go wrong with a recording
+#[derive(Debug)]
+pub enum ReplayError {
+    /// The recording file can't be read or written
+    Io { path: PathBuf, source: std::io::Error },
+    /// The recording file isn't a valid recording
+    Parse { path: PathBuf, line: usize, column: usize, message: String },
+    /// The recording file was written in another format, files without a
+    /// version being version 0
+    Version { path: PathBuf, found: u32, expected: u32 },
+    /// The replayed simulation doesn't match the recorded one anymore
+    Desync { tick: u64, expected: u64, found: u64 },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Io { path, source } => write!(f, "can't access {}: {}", path.display(), source),
+            ReplayError::Parse { path, line, column, message } => {
+                write!(f, "{}:{}:{}: invalid recording: {}", path.display(), line, column, message)
+            }
+            ReplayError::Version { path, found, expected } => write!(
+                f,
+                "{}: recording format version {} can't be read, expected version {}",
+                path.display(),
+                found,
+                expected
+            ),
+            ReplayError::Desync { tick, expected, found } => write!(
+                f,
+                "replay desync at tick {}: expected checksum {:016x}, found {:016x}",
+                tick, expected, found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ReplayError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// The inputs of every tick of a run, enough to replay it with the same seed
+pub struct Recording {
+    pub seed: u64,
+    pub level: String,
+    pub players: usize,
+    /// Inputs of all the players, for each tick
+    inputs: Vec<Vec<PlayerInput>>,
+    /// Checksum of the simulation after some ticks, by tick number
+    checksums: Vec<(u64, u64)>,
+}
+
+/// A recording as saved on disk, with the inputs run-length encoded
+#[derive(Serialize, Deserialize)]
+struct RecordingFile {
+    version: u32,
+    seed: u64,
+    level: String,
+    players: usize,
+    /// Encoded input of each player and number of ticks it is repeated
+    inputs: Vec<(Vec<u8>, u32)>,
+    checksums: Vec<(u64, u64)>,
+}
+
+/// What is read of a recording file before knowing its format
+#[derive(Deserialize)]
+struct RecordingHeader {
+    #[serde(default)]
+    version: u32,
+}
+
+impl Recording {
+    pub fn new(seed: u64, level: &str, players: usize) -> Self {
+        Recording {
+            seed,
+            level: level.to_string(),
+            players,
+            inputs: Vec::new(),
+            checksums: Vec::new(),
+        }
+    }
+
+    /// Record the inputs of the tick `simulation` just ran
+    pub fn push(&mut self, inputs: &[PlayerInput], simulation: &Simulation) {
+        self.inputs.push(inputs.to_vec());
+        if simulation.ticks % CHECKSUM_INTERVAL == 0 {
+            self.checksums.push((simulation.ticks, simulation.checksum()));
+        }
+    }
+
+    /// Number of ticks recorded
+    pub fn len(&self) -> u64 {
+        self.inputs.len() as u64
+    }
+
+    /// Inputs of the tick `tick`, counted from 1 like `Simulation::ticks`
+    pub fn inputs(&self, tick: u64) -> Option<&[PlayerInput]> {
+        self.inputs.get(tick.checked_sub(1)? as usize).map(|i| i.as_slice())
+    }
+
+    pub fn checksum(&self, tick: u64) -> Option<u64> {
+        self.checksums
+            .binary_search_by_key(&tick, |(t, _)| *t)
+            .ok()
+            .map(|i| self.checksums[i].1)
+    }
+
+    pub fn load(path: &Path) -> Result<Recording, ReplayError> {
+        let text = std::fs::read_to_string(path).map_err(|source| ReplayError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let parse_error = |e: serde_json::Error| ReplayError::Parse {
+            path: path.to_path_buf(),
+            line: e.line(),
+            column: e.column(),
+            message: e.to_string(),
+        };
+
+        // The version is checked first, the rest of another format may not parse
+        let header: RecordingHeader = serde_json::from_str(&text).map_err(parse_error)?;
+        if header.version != FORMAT_VERSION {
+            return Err(ReplayError::Version {
+                path: path.to_path_buf(),
+                found: header.version,
+                expected: FORMAT_VERSION,
+            });
+        }
+        let data: RecordingFile = serde_json::from_str(&text).map_err(parse_error)?;
+
+        let mut inputs = Vec::new();
+        for (bits, count) in data.inputs {
+            let tick: Vec<PlayerInput> = bits.into_iter().map(decode).collect();
+            inputs.extend(std::iter::repeat_n(tick, count as usize));
+        }
+        Ok(Recording {
+            seed: data.seed,
+            level: data.level,
+            players: data.players,
+            inputs,
+            checksums: data.checksums,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ReplayError> {
+        let mut runs: Vec<(Vec<u8>, u32)> = Vec::new();
+        for inputs in self.inputs.iter() {
+            let bits: Vec<u8> = inputs.iter().map(encode).collect();
+            match runs.last_mut() {
+                Some((last, count)) if *last == bits => *count += 1,
+                _ => runs.push((bits, 1)),
+            }
+        }
+        let data = RecordingFile {
+            version: FORMAT_VERSION,
+            seed: self.seed,
+            level: self.level.clone(),
+            players: self.players,
+            inputs: runs,
+            checksums: self.checksums.clone(),
+        };
+
+        let io_error = |source| ReplayError::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+        let file = File::create(path).map_err(io_error)?;
+        serde_json::to_writer(BufWriter::new(file), &data).map_err(|e| io_error(e.into()))
+    }
+}
+
+/// Pack an input in a byte: 2 bits per axis, then 1 bit for grab and 1 for throw
+fn encode(input: &PlayerInput) -> u8 {
+    let axis = |v: f32| {
+        if v < 0.0 {
+            1
+        } else if v > 0.0 {
+            2
+        } else {
+            0
+        }
+    };
+    axis(input.direction.x) | axis(input.direction.y) << 2 | (input.grab as u8) << 4 | (input.throw as u8) << 5
+}
+
+fn decode(bits: u8) -> PlayerInput {
+    let axis = |b: u8| match b & 3 {
+        1 => -1.0,
+        2 => 1.0,
+        _ => 0.0,
+    };
+    PlayerInput {
+        direction: Vec2::new(axis(bits), axis(bits >> 2)),
+        grab: bits & 16 != 0,
+        throw: bits & 32 != 0,
+    }
+}
+
+/// Play a recording back in a simulation, checking it doesn't drift away
+pub struct Replay {
+    pub recording: Recording,
+    /// Ticks simulated per tick of real time
+    pub speed: f32,
+    pub paused: bool,
+    /// First desync found, if any
+    pub desync: Option<ReplayError>,
+}
+
+impl Replay {
+    pub fn new(recording: Recording) -> Self {
+        Replay {
+            recording,
+            speed: 1.0,
+            paused: false,
+            desync: None,
+        }
+    }
+
+    pub fn is_over(&self, simulation: &Simulation) -> bool {
+        simulation.ticks >= self.recording.len()
+    }
+
+    /// Run the next recorded tick, return false once the recording is over
+    pub fn step(&mut self, simulation: &mut Simulation, dt: f32) -> Result<bool, Box<dyn Error>> {
+        let inputs = match self.recording.inputs(simulation.ticks + 1) {
+            Some(inputs) => inputs,
+            None => return Ok(false),
+        };
+        simulation.update(dt, inputs)?;
+
+        if let Some(expected) = self.recording.checksum(simulation.ticks) {
+            let found = simulation.checksum();
+            if expected != found && self.desync.is_none() {
+                self.desync = Some(ReplayError::Desync {
+                    tick: simulation.ticks,
+                    expected,
+                    found,
+                });
+            }
+        }
+        Ok(true)
+    }
+
+    /// Go to the tick `tick` of the recording
+    ///
+    /// The simulation can't go back in time, so it is restarted and
+    /// simulated again up to `tick` when seeking backward.
+    pub fn seek(&mut self, simulation: &mut Simulation, tick: u64, dt: f32) -> Result<(), Box<dyn Error>> {
+        let tick = tick.min(self.recording.len());
+        if tick < simulation.ticks {
+            simulation.restart()?;
+        }
+        while simulation.ticks < tick {
+            self.step(simulation, dt)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::tests::{scripted_inputs, simulation, PLAYERS};
+    use crate::{DEFAULT_LEVEL, FIXED_DT};
+
+    const TICKS: u64 = 600;
+
+    /// Recording of a scripted run, with the checksum after each tick
+    fn record(seed: u64) -> (Recording, Vec<u64>) {
+        let mut recorded = simulation(seed);
+        let mut recording = Recording::new(seed, DEFAULT_LEVEL, PLAYERS);
+        let mut checksums = Vec::new();
+        for tick in 1..=TICKS {
+            let inputs = scripted_inputs(tick);
+            recorded.update(FIXED_DT, &inputs).unwrap();
+            recording.push(&inputs, &recorded);
+            checksums.push(recorded.checksum());
+        }
+        (recording, checksums)
+    }
+
+    #[test]
+    fn saved_recording_replays_the_same_run() {
+        let seed = 3;
+        let (recording, checksums) = record(seed);
+
+        let path = std::env::temp_dir().join(format!("keep_you_sheep_replay_{}.json", std::process::id()));
+        recording.save(&path).unwrap();
+        let loaded = Recording::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+
+        assert_eq!((loaded.seed, loaded.level.as_str(), loaded.players), (seed, DEFAULT_LEVEL, PLAYERS));
+        assert_eq!(loaded.len(), TICKS);
+        for tick in 1..=TICKS {
+            for (found, expected) in loaded.inputs(tick).unwrap().iter().zip(scripted_inputs(tick)) {
+                assert_eq!(found.direction, expected.direction, "direction at tick {}", tick);
+                assert_eq!((found.grab, found.throw), (expected.grab, expected.throw), "buttons at tick {}", tick);
+            }
+        }
+
+        let mut replayed = simulation(loaded.seed);
+        let mut replay = Replay::new(loaded);
+        for tick in [TICKS / 2, TICKS / 5, TICKS, 1, TICKS - 1] {
+            replay.seek(&mut replayed, tick, FIXED_DT).unwrap();
+            assert_eq!(replayed.ticks, tick);
+            assert_eq!(replayed.checksum(), checksums[tick as usize - 1], "checksum at tick {}", tick);
+            assert!(replay.desync.is_none(), "{}", replay.desync.as_ref().unwrap());
+        }
+        replay.seek(&mut replayed, TICKS, FIXED_DT).unwrap();
+        assert!(replay.is_over(&replayed));
+        assert!(!replay.step(&mut replayed, FIXED_DT).unwrap());
+    }
+
+    #[test]
+    fn drifting_replay_is_reported() {
+        let (mut recording, _) = record(3);
+        recording.checksums[2].1 ^= 1;
+        let tick = recording.checksums[2].0;
+
+        let mut replayed = simulation(3);
+        let mut replay = Replay::new(recording);
+        replay.seek(&mut replayed, TICKS, FIXED_DT).unwrap();
+        assert!(matches!(replay.desync, Some(ReplayError::Desync { tick: t, .. }) if t == tick));
+    }
+}
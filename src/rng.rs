@@ -0,0 +1,62 @@
+/// Pseudo-random number generator owned by the simulation
+///
+/// Same algorithm as macroquad's global generator (PCG32), but every run
+/// has its own state, so a seed is enough to replay the same game.
+#[derive(Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+const MULTIPLIER: u64 = 6364136223846793005;
+const INCREMENT: u64 = 1442695040888963407;
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Rng { state: 0 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    /// Random number between 0 and u32::MAX
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(MULTIPLIER).wrapping_add(INCREMENT);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+
+    /// Random number between 0.0 and 1.0
+    fn unit(&mut self) -> f64 {
+        self.next_u32() as f64 / (u32::MAX as f64 + 1.0)
+    }
+
+    /// Random number in `low..high`
+    pub fn gen_range<T: RandomRange>(&mut self, low: T, high: T) -> T {
+        T::gen_range(self, low, high)
+    }
+}
+
+pub trait RandomRange: Sized {
+    fn gen_range(rng: &mut Rng, low: Self, high: Self) -> Self;
+}
+
+impl RandomRange for f32 {
+    fn gen_range(rng: &mut Rng, low: f32, high: f32) -> f32 {
+        low + (high - low) * rng.unit() as f32
+    }
+}
+
+impl RandomRange for i32 {
+    fn gen_range(rng: &mut Rng, low: i32, high: i32) -> i32 {
+        low + ((high - low) as f64 * rng.unit()) as i32
+    }
+}
+
+impl RandomRange for usize {
+    fn gen_range(rng: &mut Rng, low: usize, high: usize) -> usize {
+        low + ((high - low) as f64 * rng.unit()) as usize
+    }
+}
@@ -0,0 +1,250 @@
+use std::error::Error;
+
+use crate::campaign::Campaign;
+use crate::entities::{Entity, EntityType};
+use crate::input::PlayerInput;
+use crate::ldtk::LdtkProject;
+use crate::level::Level;
+use crate::pathfinding::Navigation;
+use crate::puppet_master;
+use crate::rng::Rng;
+use crate::spawner::spawn_entities;
+use crate::sprite_library::{self, Atlas};
+
+/// Seconds to wait before loading the next level once a level is cleared
+const LEVEL_TRANSITION: f32 = 2.0;
+
+/// Everything that makes the game go on, without any window, texture or input device
+///
+/// It only moves forward with `update`, so it can be stepped by the game
+/// loop as well as by tests, bots or a server.
+pub struct Simulation {
+    project: LdtkProject,
+    campaign: Campaign,
+    atlas: Atlas,
+    /// The only source of randomness of the game, so a seed replays the same run
+    rng: Rng,
+    pub seed: u64,
+    /// Number of players, each one with a hero
+    pub players: usize,
+    pub level: Level,
+    /// Ways around the fences of the level, found once for many ticks
+    navigation: Navigation,
+    pub entities: Vec<Entity>,
+    pub sheep_in: i32,
+    /// Sheep taken by the wolves in the current level
+    pub sheep_lost: i32,
+    /// Sheep saved in the cleared levels, less the sheep lost in them, shared
+    /// by all the players
+    pub score: i32,
+    /// Countdown before the next level in seconds, once the current one is
+    /// cleared, or before playing it again once it is lost
+    pub transition: Option<f32>,
+    /// The wolves took too many sheep to clear the current level
+    pub level_lost: bool,
+    pub campaign_over: bool,
+    /// Number of ticks run since the start
+    pub ticks: u64,
+}
+
+impl Simulation {
+    /// Load the project and the atlas, and start the campaign at `level_identifier`
+    ///
+    /// The atlas is checked against a spritesheet of `sheet_w` x `sheet_h` pixels.
+    /// The same `seed` with the same inputs always gives the same game.
+    pub fn new(
+        project_path: &str,
+        atlas_path: &str,
+        level_identifier: &str,
+        sheet_w: f32,
+        sheet_h: f32,
+        seed: u64,
+        players: usize,
+    ) -> Result<Self, Box<dyn Error>> {
+        let project = LdtkProject::load(project_path)?;
+        let campaign = Campaign::from_project(&project, level_identifier)?;
+        let level = Level::from_project(&project, &campaign.current().identifier)?;
+
+        let atlas = sprite_library::read_atlas(atlas_path, sheet_w, sheet_h)?;
+        let mut rng = Rng::new(seed);
+        let entities = spawn_entities(&level, &atlas, players, &mut rng)?;
+
+        Ok(Self {
+            project,
+            campaign,
+            atlas,
+            rng,
+            seed,
+            players,
+            level,
+            navigation: Navigation::default(),
+            entities,
+            sheep_in: 0,
+            sheep_lost: 0,
+            score: 0,
+            transition: None,
+            level_lost: false,
+            campaign_over: false,
+            ticks: 0,
+        })
+    }
+
+    pub fn campaign(&self) -> &Campaign {
+        &self.campaign
+    }
+
+    /// Start again from the first tick, with the same seed
+    pub fn restart(&mut self) -> Result<(), Box<dyn Error>> {
+        self.campaign.restart();
+        self.rng = Rng::new(self.seed);
+        self.transition = None;
+        self.campaign_over = false;
+        self.score = 0;
+        self.ticks = 0;
+        self.load_current_level()
+    }
+
+    /// Hash of the state of all the entities, to check two runs are the same
+    pub fn checksum(&self) -> u64 {
+        // FNV-1a, stable from one build to the other unlike std's hasher
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let mut feed = |value: u64| {
+            for byte in value.to_le_bytes() {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        };
+        feed(self.ticks);
+        feed(self.sheep_in as u64);
+        feed(self.score as u64);
+        for ent in self.entities.iter() {
+            feed(ent.id as u64);
+            feed(ent.position.x.to_bits() as u64);
+            feed(ent.position.y.to_bits() as u64);
+            feed(ent.velocity.x.to_bits() as u64);
+            feed(ent.velocity.y.to_bits() as u64);
+            feed(ent.height.to_bits() as u64);
+        }
+        hash
+    }
+
+    /// Replace the level and all the entities by the campaign's current level
+    fn load_current_level(&mut self) -> Result<(), Box<dyn Error>> {
+        self.level = Level::from_project(&self.project, &self.campaign.current().identifier)?;
+        self.entities = spawn_entities(&self.level, &self.atlas, self.players, &mut self.rng)?;
+        self.sheep_in = 0;
+        self.sheep_lost = 0;
+        self.level_lost = false;
+        Ok(())
+    }
+
+    /// Run one simulation tick of `dt` seconds, with the input of each player
+    pub fn update(&mut self, dt: f32, inputs: &[PlayerInput]) -> Result<(), Box<dyn Error>> {
+        self.ticks += 1;
+        if self.campaign_over {
+            return Ok(());
+        }
+
+        let weights = &self.campaign.current().flock;
+        puppet_master::play(&mut self.entities, &self.level, &mut self.navigation, weights, inputs, &mut self.rng, dt);
+
+        // Sheep taken once the level is cleared or lost don't count
+        let lost = self.entities.iter().filter(|e| e.gone && e.entity_type == EntityType::Sheep).count() as i32;
+        self.entities.retain(|e| !e.gone);
+        if self.transition.is_none() {
+            self.sheep_lost += lost;
+        }
+
+        // Need to check how many sheep are in the place
+        self.sheep_in = 0;
+        for ent in self.entities.iter() {
+            if puppet_master::is_penned(ent, &self.level) {
+                self.sheep_in += 1;
+            }
+        }
+
+        match self.transition {
+            Some(remaining) if remaining <= 0.0 => {
+                self.transition = None;
+                // A lost level is played again
+                if self.level_lost || self.campaign.advance() {
+                    self.load_current_level()?;
+                } else {
+                    self.campaign_over = true;
+                }
+            }
+            Some(remaining) => self.transition = Some(remaining - dt),
+            None => {
+                let sheep_left = self.entities.iter().filter(|e| e.entity_type == EntityType::Sheep).count() as i32;
+                if self.campaign.current().is_cleared(self.sheep_in) {
+                    // Each sheep taken by the wolves costs a point
+                    self.score += self.sheep_in - self.sheep_lost;
+                    self.transition = Some(LEVEL_TRANSITION);
+                } else if self.campaign.current().is_lost(sheep_left) {
+                    self.level_lost = true;
+                    self.transition = Some(LEVEL_TRANSITION);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use macroquad::math::Vec2;
+    use macroquad::texture::Image;
+
+    use super::*;
+    use crate::{ATLAS, DEFAULT_LEVEL, FIXED_DT, LDTK_PROJECT, SPRITESHEET};
+
+    pub const PLAYERS: usize = 2;
+
+    pub fn simulation(seed: u64) -> Simulation {
+        let image = Image::from_file_with_format(SPRITESHEET, None);
+        Simulation::new(LDTK_PROJECT, ATLAS, DEFAULT_LEVEL, image.width() as f32, image.height() as f32, seed, PLAYERS)
+            .unwrap()
+    }
+
+    /// Inputs of every player at `tick`: walking around in all the eight
+    /// directions, grabbing and throwing now and then
+    pub fn scripted_inputs(tick: u64) -> Vec<PlayerInput> {
+        (0..PLAYERS as u64)
+            .map(|player| {
+                let step = tick / 20 + player * 3;
+                let axis = |v: u64| v as f32 - 1.0;
+                PlayerInput {
+                    direction: Vec2::new(axis(step % 3), axis(step / 3 % 3)),
+                    grab: (tick + player * 7) % 45 == 0,
+                    throw: (tick + player * 7) % 45 == 30,
+                }
+            })
+            .collect()
+    }
+
+    /// Checksums after each of the `ticks` first ticks of a scripted run
+    fn run(seed: u64, ticks: u64) -> Vec<u64> {
+        let mut simulation = simulation(seed);
+        (1..=ticks)
+            .map(|tick| {
+                simulation.update(FIXED_DT, &scripted_inputs(tick)).unwrap();
+                simulation.checksum()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_and_inputs_give_the_same_run() {
+        let first = run(7, 1200);
+        let second = run(7, 1200);
+        for (tick, (a, b)) in first.iter().zip(second.iter()).enumerate() {
+            assert_eq!(a, b, "runs differ at tick {}", tick + 1);
+        }
+    }
+
+    #[test]
+    fn another_seed_gives_another_run() {
+        assert_ne!(run(7, 300), run(8, 300));
+    }
+}
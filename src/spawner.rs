@@ -0,0 +1,134 @@
+use std::error::Error;
+
+use crate::entities::{Entity, EntityType};
+use crate::level::{Level, LevelEntity, LevelError};
+use crate::puppet_master::Behaviour;
+use crate::rng::Rng;
+use crate::sprite_library::Atlas;
+
+/// Create all the entities placed in the level's entity layer
+///
+/// Supported LDtk entities are `Hero`, `Sheep`, `Wolf` and `Tree`. Their
+/// optional custom fields are:
+/// - `Count`: number of sheep scattered on free cells of the entity's area
+/// - `Behaviour`: name of the behaviour replacing the default one
+/// - `Speed`: max speed replacing the default one, in pixels per second
+/// - `Player`: player controlling a hero, heroes of players beyond `players`
+///   are not spawned
+///
+/// Other entities, unknown behaviours and areas too small for their `Count`
+/// are errors.
+pub fn spawn_entities(level: &Level, atlas: &Atlas, players: usize, rng: &mut Rng) -> Result<Vec<Entity>, Box<dyn Error>> {
+    let mut entities = Vec::new();
+    let mut id_counter = 0;
+
+    // create a vec to store all places already taked by a sheep
+    let mut entities_grid: Vec<bool> = vec![true; level.cell_w * level.cell_h];
+    // Scattered sheep don't go on the entities placed at a given position,
+    // wherever they are in the list
+    for level_entity in level.entities.iter().filter(|e| e.field_int("Count").unwrap_or(1) <= 1) {
+        take_cells(level, level_entity, &mut entities_grid);
+    }
+
+    for level_entity in level.entities.iter() {
+        let entity_type = match level_entity.identifier.as_str() {
+            "Hero" => EntityType::Hero,
+            "Sheep" => EntityType::Sheep,
+            "Wolf" => EntityType::Wolf,
+            "Tree" => EntityType::Tree,
+            other => {
+                return Err(LevelError::UnknownEntity {
+                    level: level.identifier.clone(),
+                    entity: other.to_string(),
+                }
+                .into())
+            }
+        };
+
+        let player = level_entity.field_int("Player").unwrap_or(0).max(0) as usize;
+        if entity_type == EntityType::Hero && player >= players {
+            continue;
+        }
+
+        let count = level_entity.field_int("Count").unwrap_or(1).max(1);
+        for _i in 0..count {
+            let position = if count == 1 {
+                Some((level_entity.position.x, level_entity.position.y))
+            } else {
+                free_place_in(level, level_entity, &mut entities_grid, rng)
+            };
+
+            let (x, y) = position.ok_or_else(|| LevelError::NoFreePlace {
+                level: level.identifier.clone(),
+                entity: level_entity.identifier.clone(),
+                count,
+            })?;
+
+            let mut entity = Entity::new(x, y, entity_type, id_counter, atlas)?;
+            id_counter += 1;
+
+            if let Some(name) = level_entity.field_str("Behaviour") {
+                entity.behaviour = Behaviour::from_name(name).ok_or_else(|| LevelError::InvalidField {
+                    level: level.identifier.clone(),
+                    entity: Some(level_entity.identifier.clone()),
+                    field: "Behaviour".to_string(),
+                    message: format!("unknown behaviour {}", name),
+                })?;
+            }
+            if let Behaviour::Playable { .. } = entity.behaviour {
+                entity.set_player(player);
+            }
+            if let Some(speed) = level_entity.field_float("Speed") {
+                entity.max_speed = speed as f32;
+            }
+            if let Some(strength) = level_entity.field_float("ThrowStrength") {
+                entity.throw_strength = strength as f32;
+            }
+
+            entities.push(entity);
+        }
+    }
+
+    Ok(entities)
+}
+
+/// Mark the cells under `level_entity` as taken
+fn take_cells(level: &Level, level_entity: &LevelEntity, entities_grid: &mut [bool]) {
+    let area = level_entity.area();
+    let x_min = (area.x / level.grid_size).max(0.0) as usize;
+    let y_min = (area.y / level.grid_size).max(0.0) as usize;
+    let x_max = (((area.x + area.w) / level.grid_size).ceil() as usize).min(level.cell_w);
+    let y_max = (((area.y + area.h) / level.grid_size).ceil() as usize).min(level.cell_h);
+    for y in y_min..y_max {
+        for x in x_min..x_max {
+            entities_grid[x + y * level.cell_w] = false;
+        }
+    }
+}
+
+/// Pick a random free cell in the area of `level_entity`, not already taken
+/// by another entity, and return its position in pixels
+fn free_place_in(level: &Level, level_entity: &LevelEntity, entities_grid: &mut [bool], rng: &mut Rng) -> Option<(f32, f32)> {
+    let area = level_entity.area();
+    let x_min = (area.x / level.grid_size).max(0.0) as usize;
+    let y_min = (area.y / level.grid_size).max(0.0) as usize;
+    let x_max = (((area.x + area.w) / level.grid_size) as usize).min(level.cell_w);
+    let y_max = (((area.y + area.h) / level.grid_size) as usize).min(level.cell_h);
+
+    let mut free_cells = Vec::new();
+    for y in y_min..y_max {
+        for x in x_min..x_max {
+            if level.get_int_at(x, y) == Some(0) && entities_grid[x + y * level.cell_w] {
+                free_cells.push((x, y));
+            }
+        }
+    }
+
+    if free_cells.is_empty() {
+        return None;
+    }
+
+    let (x, y) = free_cells[rng.gen_range(0, free_cells.len())];
+    entities_grid[x + y * level.cell_w] = false;
+    Some((x as f32 * level.grid_size, y as f32 * level.grid_size))
+}
@@ -1,55 +1,104 @@
+use std::rc::Rc;
+
 use macroquad::prelude::*;
 
-use crate::sprite_library::SpriteLibraryData;
+use crate::sprite_library::{Animation, Playback};
 
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub struct Sprite {
-    position: Vec2,
-    pub source_rect: Rect,
-    speed: i32,
-    frames: i32,
-    elapsed: i32,
-    current_frame: i32,
+    animation: Rc<Animation>,
+    /// Time spent on the current frame, in seconds
+    elapsed: f32,
+    current_frame: usize,
+    /// 1 when frames are played forward, -1 when played backward (ping-pong)
+    step: i32,
     pub flip_x: bool,
     play: bool,
+    /// Played once whatever the animation's playback
+    once: bool,
+    finished: bool,
+    /// The first frame has been shown but its events are not fired yet
+    starting: bool,
 }
 
 impl Sprite {
-    pub fn new(data: SpriteLibraryData) -> Self {
-        let source_rect = Rect::new(data.x as f32, data.y as f32, data.w as f32, data.h as f32);
+    pub fn new(animation: Rc<Animation>) -> Self {
         Self {
-            position: Vec2::ZERO,
-            source_rect,
-            frames: data.frame,
-            speed: data.speed,
-            elapsed: 0,
+            animation,
+            elapsed: 0.0,
             current_frame: 0,
+            step: 1,
             flip_x: false,
             play: true,
+            once: false,
+            finished: false,
+            starting: true,
         }
     }
 
-    fn animate(&mut self) {
-        self.elapsed += 1;
-        if self.elapsed > self.speed {
-            self.current_frame = (self.current_frame + 1) % self.frames;
-            self.elapsed = 0;
+    /// Make the animation go on for `dt` seconds, and add the events of all
+    /// the frames reached to `events`
+    pub fn update(&mut self, dt: f32, events: &mut Vec<String>) {
+        if !self.play || self.finished {
+            return;
+        }
+        if self.starting {
+            self.starting = false;
+            events.extend(self.animation.frames[self.current_frame].events.iter().cloned());
+        }
+        self.elapsed += dt;
+
+        // A long dt can skip several frames
+        loop {
+            let duration = self.animation.frames[self.current_frame].duration;
+            if self.elapsed < duration {
+                break;
+            }
+            self.elapsed -= duration;
+            if !self.next_frame() {
+                self.finished = true;
+                self.elapsed = 0.0;
+                break;
+            }
+            events.extend(self.animation.frames[self.current_frame].events.iter().cloned());
         }
     }
 
-    pub fn draw_sprite(&mut self, texture: Texture2D, scale: f32) {
-        if self.play {
-            self.animate();
+    /// Go to the next frame, return false if the animation is over
+    fn next_frame(&mut self) -> bool {
+        let last = self.animation.frames.len() - 1;
+        let playback = if self.once { Playback::Once } else { self.animation.playback };
+        match playback {
+            Playback::Loop => self.current_frame = if self.current_frame == last { 0 } else { self.current_frame + 1 },
+            Playback::Once => {
+                if self.current_frame == last {
+                    return false;
+                }
+                self.current_frame += 1;
+            }
+            Playback::PingPong => {
+                if last == 0 {
+                    return true;
+                }
+                if (self.step > 0 && self.current_frame == last) || (self.step < 0 && self.current_frame == 0) {
+                    self.step = -self.step;
+                }
+                self.current_frame = (self.current_frame as i32 + self.step) as usize;
+            }
         }
-        let current_source_rect = Rect {
-            x: self.source_rect.x + self.source_rect.w * self.current_frame as f32,
-            y: self.source_rect.y,
-            w: self.source_rect.w,
-            h: self.source_rect.h,
-        };
+        true
+    }
+
+    /// True when an animation played once has shown its last frame
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn draw_sprite(&self, position: Vec2, texture: Texture2D, scale: f32, color: Color) {
+        let source = self.animation.frames[self.current_frame].source;
         let params = DrawTextureParams {
-            source: Some(current_source_rect),
-            dest_size: Some(Vec2::new(self.source_rect.w * scale, self.source_rect.h * scale)),
+            source: Some(source),
+            dest_size: Some(Vec2::new(source.w * scale, source.h * scale)),
             rotation: 0.0,
             flip_x: self.flip_x,
             flip_y: false,
@@ -58,29 +107,65 @@ impl Sprite {
 
         draw_texture_ex(
             texture,
-            (self.position.x * scale).round(),
-            (self.position.y * scale).round(),
-            WHITE,
+            (position.x * scale).round(),
+            (position.y * scale).round(),
+            color,
             params,
         );
     }
 
-    pub fn set_animation(&mut self, data: &SpriteLibraryData) {
-        self.source_rect = Rect::new(data.x as f32, data.y as f32, data.w as f32, data.h as f32);
-        self.frames = data.frame;
-        self.speed = data.speed;
-    }
-
-    pub fn set_position_to(&mut self, position: Vec2) {
-        self.position = position;
+    pub fn set_animation(&mut self, animation: &Rc<Animation>) {
+        self.animation = Rc::clone(animation);
     }
 
     pub fn play(&mut self) {
         self.play = true;
+        self.once = false;
+        self.finished = false;
+        self.starting = true;
         self.current_frame = 0;
+        self.elapsed = 0.0;
+        self.step = 1;
+    }
+
+    /// Play the animation from its first frame to its last one, then stop,
+    /// even if it is meant to loop
+    pub fn play_once(&mut self) {
+        self.play();
+        self.once = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sprite_library::Frame;
+
+    fn animation(playback: Playback) -> Rc<Animation> {
+        let frame = |event: &str| Frame {
+            source: Rect::new(0.0, 0.0, 8.0, 8.0),
+            duration: 0.1,
+            events: vec![event.to_string()],
+        };
+        Rc::new(Animation { frames: vec![frame("first"), frame("last")], playback })
+    }
+
+    #[test]
+    fn looping_animation_played_once_ends() {
+        for playback in [Playback::Loop, Playback::PingPong, Playback::Once] {
+            let mut sprite = Sprite::new(animation(playback));
+            sprite.play_once();
+            let mut events = Vec::new();
+            for _ in 0..10 {
+                sprite.update(0.05, &mut events);
+            }
+            assert!(sprite.is_finished(), "{:?} never ends", playback);
+            assert_eq!(events, ["first", "last"]);
+
+            // Played again normally, it goes back to its own playback
+            sprite.play();
+            sprite.update(1.0, &mut events);
+            assert_eq!(sprite.is_finished(), playback == Playback::Once);
+        }
     }
-    //pub fn stop(&mut self) {
-    //    self.play = false;
-    //    self.current_frame = 0;
-    //}
 }
@@ -1,43 +1,228 @@
+use macroquad::math::Rect;
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
-use crate::sprite::Sprite;
+use crate::aseprite;
 
-#[derive(Deserialize, Debug, Clone, Copy)]
+/// Everything that can go wrong with the sprite atlas
+#[derive(Debug)]
+pub enum AssetError {
+    /// The atlas file can't be read
+    Io { path: PathBuf, source: std::io::Error },
+    /// The atlas file isn't a valid list of animations
+    Parse { path: PathBuf, line: usize, column: usize, message: String },
+    /// Two animations of the atlas have the same name
+    DuplicateAnimation { path: PathBuf, name: String },
+    /// Some frames of the animation are outside of the spritesheet, or
+    /// last no time
+    OutOfSheet { path: PathBuf, name: String },
+    /// An event is set on a frame the animation doesn't have
+    EventOutOfAnimation { path: PathBuf, name: String, frame: String },
+    /// The Aseprite export is valid JSON, but its content doesn't make sense
+    Aseprite { path: PathBuf, message: String },
+    /// An animation is needed but not in the atlas
+    MissingAnimation { name: String },
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssetError::Io { path, source } => write!(f, "can't read {}: {}", path.display(), source),
+            AssetError::Parse { path, line, column, message } => {
+                write!(f, "{}:{}:{}: {}", path.display(), line, column, message)
+            }
+            AssetError::DuplicateAnimation { path, name } => {
+                write!(f, "{}: animation {} is defined twice", path.display(), name)
+            }
+            AssetError::OutOfSheet { path, name } => {
+                write!(f, "{}: animation {} doesn't fit in the spritesheet or has frames without duration", path.display(), name)
+            }
+            AssetError::EventOutOfAnimation { path, name, frame } => {
+                write!(f, "{}: animation {} has no frame {} for its events", path.display(), name, frame)
+            }
+            AssetError::Aseprite { path, message } => write!(f, "{}: invalid Aseprite export: {}", path.display(), message),
+            AssetError::MissingAnimation { name } => write!(f, "no animation named {} in the atlas", name),
+        }
+    }
+}
+
+impl std::error::Error for AssetError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AssetError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// A strip animation: `frame` frames of `w` x `h` pixels side by side, each
+/// one displayed `duration` seconds
+///
+/// `events` gives the names of the events fired when entering a frame, by
+/// frame index.
+#[derive(Deserialize, Debug, Clone)]
 pub struct SpriteLibraryData {
     pub x: i32,
     pub y: i32,
     pub w: i32,
     pub h: i32,
     pub frame: i32,
-    pub speed: i32,
-}
-
-pub fn read_atlas() -> HashMap<String, SpriteLibraryData>{
-
-    let atlas: HashMap<String, SpriteLibraryData> = HashMap::from([
-        ("hero_walk_right".to_string(), SpriteLibraryData{x: 0, y: 0, w: 16, h: 16, frame: 4, speed: 10}),
-        ("hero_idle_right".to_string(), SpriteLibraryData{x: 0, y: 0, w: 16, h: 16, frame: 1, speed: 10}),
-        ("hero_walk_left".to_string(), SpriteLibraryData{ x: 0, y: 16, w: 16, h: 16, frame: 4, speed: 10}),
-        ("hero_idle_left".to_string(), SpriteLibraryData{ x: 0, y: 16, w: 16, h: 16, frame: 1, speed: 10 }),
-        ("hero_walk_down".to_string(),  SpriteLibraryData{ x: 0, y: 48, w: 16, h: 16, frame: 4, speed: 10 }),
-        ("hero_walk_up".to_string(), SpriteLibraryData{ x: 0, y: 32, w: 16, h: 16, frame: 4, speed: 10}),
-        ("hero_idle_up".to_string(),  SpriteLibraryData{ x: 0, y: 32, w: 16, h: 16, frame: 1, speed: 10 }),
-        ("hero_idle_down".to_string(), SpriteLibraryData{ x: 0, y: 48, w: 16, h: 16, frame: 1, speed: 10 }),
-        ("sheep_walk_right".to_string(), SpriteLibraryData{ x: 64, y: 0, w: 16, h: 16, frame: 4, speed: 10 }),
-        ("sheep_walk_left".to_string(), SpriteLibraryData{ x: 64, y: 16, w: 16, h: 16, frame: 4, speed: 10 }),
-        ("sheep_idle_right".to_string(), SpriteLibraryData{ x: 64, y: 0, w: 16, h: 16, frame: 1, speed: 10 }),
-        ("sheep_idle_left".to_string(), SpriteLibraryData{ x: 64, y: 16, w: 16, h: 16, frame: 1, speed: 10 }),
-        ("sheep_idle_down".to_string(), SpriteLibraryData{ x: 64, y: 48, w: 16, h: 16, frame: 1, speed: 10 }),
-        ("sheep_idle_up".to_string(), SpriteLibraryData{ x: 64, y: 32, w: 16, h: 16, frame: 1, speed: 10 }),
-        ("sheep_walk_down".to_string(), SpriteLibraryData{ x: 64, y: 48, w: 16, h: 16, frame: 4, speed: 10 }),
-        ("sheep_walk_up".to_string(), SpriteLibraryData{ x: 64, y: 32, w: 16, h: 16, frame: 4, speed: 10 }),
-        ("wolf_walk_right".to_string(), SpriteLibraryData{ x: 128, y: 0, w: 32, h: 16, frame: 8, speed: 5 }),
-        ("wolf_walk_left".to_string(), SpriteLibraryData{ x: 128, y: 16, w: 32, h: 16, frame: 8, speed: 5 }),
-        ("wolf_idle_right".to_string(), SpriteLibraryData{ x: 128, y: 32, w: 32, h: 16, frame: 5, speed: 10 }),
-        ("wolf_idle_left".to_string(), SpriteLibraryData{ x: 128, y: 48, w: 32, h: 16, frame: 5, speed: 10 }),
-        ("wolf_sleep_right".to_string(), SpriteLibraryData{ x: 448, y: 32, w: 32, h: 16, frame: 6, speed: 10 }),
-        ("wolf_sleep_right".to_string(), SpriteLibraryData{ x: 448, y: 48, w: 32, h: 16, frame: 6, speed: 10 })
-    ]);
+    pub duration: f32,
+    #[serde(default)]
+    pub playback: Playback,
+    /// Frame indexes are strings, serde can't read them as numbers in an
+    /// untagged enum
+    #[serde(default)]
+    pub events: HashMap<String, Vec<String>>,
+}
+
+/// A frame of an animation
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// Where the frame is in the spritesheet
+    pub source: Rect,
+    /// How long the frame is displayed, in seconds
+    pub duration: f32,
+    /// Events fired when the frame is displayed (footstep, bite, ...)
+    pub events: Vec<String>,
+}
+
+/// How an animation goes on after its last frame
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Playback {
+    /// Start again from the first frame
+    #[default]
+    Loop,
+    /// Stay on the last frame
+    Once,
+    /// Play the frames backward, then forward again, and so on
+    PingPong,
+}
+
+#[derive(Debug, Clone)]
+pub struct Animation {
+    pub frames: Vec<Frame>,
+    pub playback: Playback,
+}
+
+impl Animation {
+    pub fn from_strip(data: &SpriteLibraryData) -> Animation {
+        let frames = (0..data.frame)
+            .map(|i| Frame {
+                source: Rect::new((data.x + data.w * i) as f32, data.y as f32, data.w as f32, data.h as f32),
+                duration: data.duration,
+                events: data.events.get(&i.to_string()).cloned().unwrap_or_default(),
+            })
+            .collect();
+        Animation { frames, playback: data.playback }
+    }
+
+    fn fits_in(&self, sheet_w: f32, sheet_h: f32) -> bool {
+        !self.frames.is_empty()
+            && self.frames.iter().all(|f| {
+                f.source.x >= 0.0
+                    && f.source.y >= 0.0
+                    && f.source.w > 0.0
+                    && f.source.h > 0.0
+                    && f.source.right() <= sheet_w
+                    && f.source.bottom() <= sheet_h
+                    && f.duration > 0.0
+            })
+    }
+}
+
+/// All the animations, by name
+pub type Atlas = HashMap<String, Rc<Animation>>;
+
+/// An entry of the atlas file
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AtlasEntry {
+    /// A single strip animation
+    Strip {
+        name: String,
+        #[serde(flatten)]
+        data: SpriteLibraryData,
+    },
+    /// All the tagged animations of an Aseprite JSON export, named
+    /// `prefix` + tag name
+    Aseprite {
+        aseprite: PathBuf,
+        #[serde(default)]
+        prefix: String,
+    },
+}
+
+/// Read the atlas file, a JSON list of strip animations and Aseprite exports
+///
+/// Aseprite files are relative to the atlas file. Every animation must have a
+/// unique name, and all its frames must fit in a spritesheet of `sheet_w` x
+/// `sheet_h` pixels.
+pub fn read_atlas<P: AsRef<Path>>(path: P, sheet_w: f32, sheet_h: f32) -> Result<Atlas, AssetError> {
+    let path = path.as_ref();
+    let entries: Vec<AtlasEntry> = read_json(path)?;
+
+    let mut animations = Vec::new();
+    for entry in entries {
+        match entry {
+            AtlasEntry::Strip { name, data } => {
+                let frames = 0..data.frame.max(0) as usize;
+                if let Some(frame) = data.events.keys().find(|f| !f.parse().is_ok_and(|f| frames.contains(&f))) {
+                    return Err(AssetError::EventOutOfAnimation {
+                        path: path.to_path_buf(),
+                        name,
+                        frame: frame.clone(),
+                    });
+                }
+                animations.push((name, path.to_path_buf(), Animation::from_strip(&data)))
+            }
+            AtlasEntry::Aseprite { aseprite, prefix } => {
+                let aseprite_path = path.parent().unwrap_or_else(|| Path::new("")).join(aseprite);
+                for (tag, animation) in aseprite::import(&aseprite_path)? {
+                    animations.push((format!("{}{}", prefix, tag), aseprite_path.clone(), animation));
+                }
+            }
+        }
+    }
+
+    let mut atlas = HashMap::new();
+    for (name, origin, animation) in animations {
+        if !animation.fits_in(sheet_w, sheet_h) {
+            return Err(AssetError::OutOfSheet { path: origin, name });
+        }
+        if atlas.insert(name.clone(), Rc::new(animation)).is_some() {
+            return Err(AssetError::DuplicateAnimation { path: origin, name });
+        }
+    }
+
+    Ok(atlas)
+}
+
+/// Read and parse a JSON asset file
+pub fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T, AssetError> {
+    let file = File::open(path).map_err(|source| AssetError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    serde_json::from_reader(BufReader::new(file)).map_err(|e| AssetError::Parse {
+        path: path.to_path_buf(),
+        line: e.line(),
+        column: e.column(),
+        message: e.to_string(),
+    })
+}
+
+/// Get the animation `name` from the atlas
+pub fn get_animation(atlas: &Atlas, name: &str) -> Result<Rc<Animation>, AssetError> {
     atlas
+        .get(name)
+        .cloned()
+        .ok_or_else(|| AssetError::MissingAnimation { name: name.to_string() })
 }
@@ -0,0 +1,174 @@
+use macroquad::math::{Rect, Vec2};
+
+/// Gap under which two boxes touch, in pixels, so that rounding errors
+/// don't let an entity in contact go through
+const CONTACT_EPSILON: f32 = 0.01;
+
+/// Something an entity ran into during a tick
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Contact {
+    /// Unit vector pointing away from what was hit
+    pub normal: Vec2,
+    /// Id of the entity hit, None for the level
+    pub entity: Option<u32>,
+}
+
+/// Move of a box along one axis, stopped at the first obstacle in the way
+///
+/// The box goes exactly to contact, however far it moves in a tick, so it
+/// never goes through thin walls nor stops before them.
+pub struct Sweep {
+    rect: Rect,
+    on_x: bool,
+    /// Sign of the move
+    dir: f32,
+    /// How far the box can go
+    distance: f32,
+    pub contact: Option<Contact>,
+}
+
+impl Sweep {
+    /// Move `rect` of `delta` pixels, along x if `on_x`, else along y
+    pub fn new(rect: Rect, on_x: bool, delta: f32) -> Self {
+        Sweep {
+            rect,
+            on_x,
+            dir: if delta == 0.0 { 0.0 } else { delta.signum() },
+            distance: delta.abs(),
+            contact: None,
+        }
+    }
+
+    /// What the box may touch, from where it is to where it wants to go
+    pub fn area(&self) -> Rect {
+        self.rect.combine_with(self.rect.offset(self.axis() * self.delta()))
+    }
+
+    /// Signed length of the move once stopped
+    pub fn delta(&self) -> f32 {
+        self.dir * self.distance
+    }
+
+    fn axis(&self) -> Vec2 {
+        if self.on_x {
+            Vec2::X
+        } else {
+            Vec2::Y
+        }
+    }
+
+    /// Box bounds on the axis of the move, then on the other one
+    fn bounds(&self, rect: &Rect) -> ((f32, f32), (f32, f32)) {
+        if self.on_x {
+            ((rect.left(), rect.right()), (rect.top(), rect.bottom()))
+        } else {
+            ((rect.top(), rect.bottom()), (rect.left(), rect.right()))
+        }
+    }
+
+    fn stop(&mut self, gap: f32, entity: Option<u32>) {
+        if gap < self.distance {
+            self.distance = gap.max(0.0);
+            // Pointing back against the move
+            self.contact = Some(Contact { normal: self.axis() * -self.dir, entity });
+        }
+    }
+
+    /// Stop before `obstacle`, the box of the entity `entity` or of the level
+    ///
+    /// Obstacles already overlapping the box are ignored, to let it get out.
+    pub fn against(&mut self, obstacle: &Rect, entity: Option<u32>) {
+        if self.dir == 0.0 {
+            return;
+        }
+        let ((min, max), (cross_min, cross_max)) = self.bounds(&self.rect);
+        let ((o_min, o_max), (o_cross_min, o_cross_max)) = self.bounds(obstacle);
+
+        // Only touching on the side doesn't stop a box sliding along
+        if cross_min >= o_cross_max - CONTACT_EPSILON || o_cross_min >= cross_max - CONTACT_EPSILON {
+            return;
+        }
+        let gap = if self.dir > 0.0 { o_min - max } else { min - o_max };
+        if gap > -CONTACT_EPSILON {
+            self.stop(gap, entity);
+        }
+    }
+
+    /// Stop at the edge of `bounds`, never going further out of it
+    pub fn inside(&mut self, bounds: &Rect) {
+        if self.dir == 0.0 {
+            return;
+        }
+        let ((min, max), _) = self.bounds(&self.rect);
+        let ((b_min, b_max), _) = self.bounds(bounds);
+        let room = if self.dir > 0.0 { b_max - max } else { min - b_min };
+        self.stop(room, None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOX: Rect = Rect { x: 0.0, y: 0.0, w: 10.0, h: 10.0 };
+
+    #[test]
+    fn fast_box_stops_at_a_thin_wall() {
+        let wall = Rect::new(100.0, -20.0, 1.0, 50.0);
+        let mut sweep = Sweep::new(BOX, true, 500.0);
+        sweep.against(&wall, Some(7));
+        assert_eq!(sweep.delta(), 90.0);
+        assert_eq!(sweep.contact, Some(Contact { normal: -Vec2::X, entity: Some(7) }));
+
+        let mut sweep = Sweep::new(BOX.offset(Vec2::new(200.0, 0.0)), true, -500.0);
+        sweep.against(&wall, None);
+        assert_eq!(sweep.delta(), -99.0);
+        assert_eq!(sweep.contact, Some(Contact { normal: Vec2::X, entity: None }));
+    }
+
+    #[test]
+    fn box_in_contact_stays_in_contact() {
+        let mut sweep = Sweep::new(BOX, false, 3.0);
+        sweep.against(&Rect::new(-5.0, 10.0, 20.0, 4.0), None);
+        assert_eq!(sweep.delta(), 0.0);
+        assert_eq!(sweep.contact.map(|c| c.normal), Some(-Vec2::Y));
+    }
+
+    #[test]
+    fn box_slides_past_the_side_of_an_obstacle() {
+        // Touching the side, then missing it by a pixel
+        for obstacle in [Rect::new(20.0, 10.0, 10.0, 10.0), Rect::new(20.0, -11.0, 10.0, 10.0)] {
+            let mut sweep = Sweep::new(BOX, true, 50.0);
+            sweep.against(&obstacle, None);
+            assert_eq!(sweep.delta(), 50.0);
+            assert_eq!(sweep.contact, None);
+        }
+    }
+
+    #[test]
+    fn overlapping_obstacle_is_ignored() {
+        let mut sweep = Sweep::new(BOX, true, 20.0);
+        sweep.against(&Rect::new(5.0, 0.0, 10.0, 10.0), Some(1));
+        assert_eq!(sweep.delta(), 20.0);
+        assert_eq!(sweep.contact, None);
+
+        // Still stopped by what is further
+        sweep.against(&Rect::new(25.0, 0.0, 10.0, 10.0), Some(2));
+        assert_eq!(sweep.delta(), 15.0);
+        assert_eq!(sweep.contact.and_then(|c| c.entity), Some(2));
+    }
+
+    #[test]
+    fn box_stays_inside_the_bounds() {
+        let bounds = Rect::new(-30.0, -30.0, 100.0, 100.0);
+        let mut sweep = Sweep::new(BOX, false, -100.0);
+        sweep.inside(&bounds);
+        assert_eq!(sweep.delta(), -30.0);
+        assert_eq!(sweep.contact, Some(Contact { normal: Vec2::Y, entity: None }));
+
+        // Already out, it doesn't go further
+        let mut sweep = Sweep::new(BOX.offset(Vec2::new(70.0, 0.0)), true, 5.0);
+        sweep.inside(&bounds);
+        assert_eq!(sweep.delta(), 0.0);
+    }
+}